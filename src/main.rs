@@ -1,8 +1,111 @@
 mod merger;
 mod ui;
 
+use std::path::PathBuf;
+
+use merger::channel_mask::survey_channels;
+use merger::config::Config;
+use merger::extract::{extract_events, EventRange};
+use merger::pedestal::analyze_pedestal_run;
 use ui::app::MergerApp;
 
+/// Read the config file and run number which start the arguments of every command, checking the number of arguments.
+/// Problems are logged along with the usage of the command.
+fn parse_command_args(args: &[String], n_args: usize, usage: &str) -> Option<(Config, i32)> {
+    if args.len() != n_args {
+        log::error!("{}", usage);
+        return None;
+    }
+    let config = match Config::read_config_file(&PathBuf::from(&args[0])) {
+        Ok(conf) => conf,
+        Err(e) => {
+            log::error!("{}", e);
+            return None;
+        }
+    };
+    match args[1].parse() {
+        Ok(run) => Some((config, run)),
+        Err(_) => {
+            log::error!("Invalid run number {}\n{}", args[1], usage);
+            None
+        }
+    }
+}
+
+const EXTRACT_USAGE: &str =
+    "Usage: rusted_graw extract <config.yaml> <run> <events> <output.h5|output.json>\n  events: comma separated event ids or inclusive ranges, i.e. 12,100-200";
+
+/// Handle the extract command: pull a set of events directly from the raw data of a run. Returns true on success.
+fn run_extract_command(args: &[String]) -> bool {
+    let Some((config, run_number)) = parse_command_args(args, 4, EXTRACT_USAGE) else {
+        return false;
+    };
+    let mut ranges: Vec<EventRange> = Vec::new();
+    for token in args[2].split(',') {
+        match EventRange::try_from(token) {
+            Ok(range) => ranges.push(range),
+            Err(e) => {
+                log::error!(
+                    "Invalid event selection {}: {}\n{}",
+                    token,
+                    e,
+                    EXTRACT_USAGE
+                );
+                return false;
+            }
+        }
+    }
+    match extract_events(&config, run_number, &ranges, &PathBuf::from(&args[3])) {
+        Ok(_) => {
+            log::info!("Extraction complete.");
+            true
+        }
+        Err(e) => {
+            log::error!("Extraction error: {}", e);
+            false
+        }
+    }
+}
+
+const PEDESTAL_USAGE: &str = "Usage: rusted_graw pedestal <config.yaml> <run> <output.h5>";
+
+/// Handle the pedestal command: analyze a pedestal run and write its pedestal table. Returns true on success.
+fn run_pedestal_command(args: &[String]) -> bool {
+    let Some((config, run_number)) = parse_command_args(args, 3, PEDESTAL_USAGE) else {
+        return false;
+    };
+    match analyze_pedestal_run(&config, run_number, &PathBuf::from(&args[2])) {
+        Ok(_) => {
+            log::info!("Pedestal analysis complete.");
+            true
+        }
+        Err(e) => {
+            log::error!("Pedestal analysis error: {}", e);
+            false
+        }
+    }
+}
+
+const SURVEY_USAGE: &str = "Usage: rusted_graw survey <config.yaml> <run> <mask.csv>";
+
+/// Handle the survey command: find the dead, hot, and noisy channels of a run and write a channel mask. Returns true on
+/// success.
+fn run_survey_command(args: &[String]) -> bool {
+    let Some((config, run_number)) = parse_command_args(args, 3, SURVEY_USAGE) else {
+        return false;
+    };
+    match survey_channels(&config, run_number, &PathBuf::from(&args[2])) {
+        Ok(_) => {
+            log::info!("Channel survey complete.");
+            true
+        }
+        Err(e) => {
+            log::error!("Channel survey error: {}", e);
+            false
+        }
+    }
+}
+
 #[allow(unreachable_code, dead_code)]
 fn main() {
     simplelog::TermLogger::init(
@@ -13,6 +116,21 @@ fn main() {
     )
     .unwrap();
 
+    // Commands exit with a non-zero status on failure, so that scripts can detect it
+    let args: Vec<String> = std::env::args().collect();
+    let command: Option<fn(&[String]) -> bool> = match args.get(1).map(|arg| arg.as_str()) {
+        Some("extract") => Some(run_extract_command),
+        Some("pedestal") => Some(run_pedestal_command),
+        Some("survey") => Some(run_survey_command),
+        _ => None,
+    };
+    if let Some(command) = command {
+        if !command(&args[2..]) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let mut native_options = eframe::NativeOptions::default();
     native_options.viewport = eframe::egui::ViewportBuilder::default()
         .with_title("rusted_graw")
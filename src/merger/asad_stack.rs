@@ -2,6 +2,7 @@ use std::collections::VecDeque;
 use std::path::{Path, PathBuf};
 
 use super::error::{AsadStackError, GrawFileError};
+use super::frame_index::{FrameIndex, INDEX_EXTENSION};
 use super::graw_file::GrawFile;
 use super::graw_frame::{FrameMetadata, GrawFrame};
 
@@ -16,11 +17,17 @@ use super::graw_frame::{FrameMetadata, GrawFrame};
 /// ## Why
 /// This is more advantageous than simply opening all files, because we don't want to have to search through all possible files to find the earliest frame
 /// when we dont have to. It can also save some memory/optimization by not having to buffer up all of the files around.
+///
+/// ## Random Access
+/// A stack can also be given a FrameIndex (see frame_index.rs), either read from the sidecar file in the data directory or built by
+/// scanning the files. With an index the stack can seek directly to an event id without reading the frames before it.
 #[allow(dead_code)]
 #[derive(Debug)]
 pub struct AsadStack {
     active_file: GrawFile,
     file_stack: VecDeque<PathBuf>,
+    file_list: Vec<PathBuf>, // All of the files of the stack, in order
+    frame_index: Option<FrameIndex>,
     cobo_number: i32,
     asad_number: i32,
     parent_path: PathBuf,
@@ -40,11 +47,14 @@ impl AsadStack {
 
         let (mut file_stack, total_stack_size_bytes) =
             Self::get_file_stack(&parent_path, &cobo_number, &asad_number)?;
+        let file_list: Vec<PathBuf> = file_stack.iter().cloned().collect();
         if let Some(path) = file_stack.pop_front() {
             //Activate the first file
             Ok(AsadStack {
                 active_file: GrawFile::new(&path)?,
                 file_stack,
+                file_list,
+                frame_index: None,
                 cobo_number,
                 asad_number,
                 parent_path,
@@ -90,6 +100,85 @@ impl AsadStack {
         Ok(self.active_file.get_next_frame()?)
     }
 
+    /// Path to the sidecar file holding the FrameIndex of this stack
+    pub fn get_index_path(&self) -> PathBuf {
+        self.parent_path.join(format!(
+            "CoBo{}_AsAd{}.{}",
+            self.cobo_number, self.asad_number, INDEX_EXTENSION
+        ))
+    }
+
+    /// Attempt to read the FrameIndex from the sidecar file. Returns false if there is no sidecar, or if the sidecar
+    /// does not match the files currently in the stack.
+    pub fn load_frame_index(&mut self) -> Result<bool, AsadStackError> {
+        let index_path = self.get_index_path();
+        if !index_path.exists() {
+            return Ok(false);
+        }
+        let index = FrameIndex::read(&index_path)?;
+        if !index.is_valid_for(&self.file_list) {
+            log::warn!(
+                "Frame index {} is out of date with the data and will be ignored.",
+                index_path.display()
+            );
+            return Ok(false);
+        }
+        self.frame_index = Some(index);
+        Ok(true)
+    }
+
+    /// Scan all of the files in the stack to build the FrameIndex, and write it to the sidecar file. Failing to write
+    /// the sidecar (i.e. read-only data directories) is not an error; the index is still kept in memory.
+    pub fn build_frame_index(&mut self) -> Result<(), AsadStackError> {
+        let index = FrameIndex::build(&self.file_list)?;
+        let index_path = self.get_index_path();
+        if let Err(e) = index.write(&index_path) {
+            log::warn!(
+                "Could not write frame index {}: {}",
+                index_path.display(),
+                e
+            );
+        }
+        self.frame_index = Some(index);
+        Ok(())
+    }
+
+    /// Load the FrameIndex from its sidecar if possible, otherwise build it
+    pub fn load_or_build_frame_index(&mut self) -> Result<(), AsadStackError> {
+        if self.frame_index.is_some() || self.load_frame_index()? {
+            return Ok(());
+        }
+        self.build_frame_index()
+    }
+
+    /// Move the stack to the first frame with an event id greater than or equal to the requested one, loading or building the FrameIndex
+    /// if needed. Returns false if there is no such frame; in that case the stack is ended.
+    pub fn seek_to_event(&mut self, event_id: u32) -> Result<bool, AsadStackError> {
+        self.load_or_build_frame_index()?;
+        let index = match self.frame_index.as_ref() {
+            Some(index) => index,
+            None => return Ok(false),
+        };
+        let file_index = match index.find_first_frame(event_id) {
+            Some(entry) => entry.file_index,
+            None => {
+                self.file_stack.clear();
+                self.is_ended = true;
+                return Ok(false);
+            }
+        };
+
+        let mut file = GrawFile::new(&self.file_list[file_index as usize])?;
+        file.seek_to_event(index, file_index, event_id)?;
+        self.active_file = file;
+        self.file_stack = self.file_list[(file_index as usize + 1)..]
+            .iter()
+            .cloned()
+            .collect();
+        self.is_ended = false;
+        Ok(true)
+    }
+
     /// The total size of the stack data in bytes
     pub fn get_stack_size_bytes(&self) -> &u64 {
         &self.total_stack_size_bytes
@@ -103,15 +192,23 @@ impl AsadStack {
         &self.asad_number
     }
 
+    /// All of the files of the stack, including those already read
+    pub fn get_file_list_ref(&self) -> &Vec<PathBuf> {
+        &self.file_list
+    }
+
+    #[allow(dead_code)]
     pub fn get_file_stack_ref(&self) -> &VecDeque<PathBuf> {
         &self.file_stack
     }
 
+    #[allow(dead_code)]
     pub fn get_active_file(&self) -> &GrawFile {
         &self.active_file
     }
 
     /// Returns true if there is still data to be read from this stack. Returns false if the stack is finished.
+    #[allow(dead_code)]
     pub fn is_not_ended(&self) -> bool {
         !self.is_ended
     }
@@ -0,0 +1,126 @@
+use ndarray::ArrayView1;
+
+use super::config::{BaselineConfig, BaselineMethod};
+
+/// # Baseline
+/// The estimated baseline of a trace and the RMS of the samples used to estimate it
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Baseline {
+    pub value: f64,
+    pub rms: f64,
+}
+
+/// Estimate the baseline of a trace from the buckets which were read (see Event). Returns None if the trace has no samples to
+/// estimate from. Mean and Median only use the first n_buckets buckets of the trace, so partial readout traces with no samples
+/// there have no baseline.
+pub fn estimate_baseline(
+    trace: &ArrayView1<i16>,
+    read: &[bool],
+    config: &BaselineConfig,
+) -> Option<Baseline> {
+    let n_used = match config.method {
+        BaselineMethod::Mean | BaselineMethod::Median => config.n_buckets.clamp(1, trace.len()),
+        BaselineMethod::Percentile | BaselineMethod::Iterative => trace.len(),
+    };
+    let samples: Vec<f64> = trace
+        .iter()
+        .zip(read.iter())
+        .take(n_used)
+        .filter(|(_, read)| **read)
+        .map(|(sample, _)| *sample as f64)
+        .collect();
+    if samples.is_empty() {
+        return None;
+    }
+
+    match config.method {
+        BaselineMethod::Mean => Some(with_rms(mean(&samples), &samples)),
+        BaselineMethod::Median => {
+            let mut used = samples;
+            used.sort_by(f64::total_cmp);
+            // Same bucket twice for an odd length
+            let value = (used[(used.len() - 1) / 2] + used[used.len() / 2]) / 2.0;
+            Some(with_rms(value, &used))
+        }
+        BaselineMethod::Percentile => {
+            let mut sorted = samples;
+            sorted.sort_by(f64::total_cmp);
+            let fraction = config.percentile.clamp(0.0, 100.0) / 100.0;
+            let idx = (fraction * (sorted.len() - 1) as f64).round() as usize;
+            // The samples at or below the percentile are taken as the baseline region
+            Some(with_rms(sorted[idx], &sorted[..=idx]))
+        }
+        BaselineMethod::Iterative => {
+            let mut used = samples;
+            let mut baseline = with_rms(mean(&used), &used);
+            for _ in 0..config.max_iterations {
+                let limit = config.n_sigma * baseline.rms;
+                let kept: Vec<f64> = used
+                    .iter()
+                    .copied()
+                    .filter(|sample| (sample - baseline.value).abs() <= limit)
+                    .collect();
+                if kept.is_empty() || kept.len() == used.len() {
+                    break;
+                }
+                used = kept;
+                baseline = with_rms(mean(&used), &used);
+            }
+            Some(baseline)
+        }
+    }
+}
+
+/// Mean of a non-empty slice
+fn mean(samples: &[f64]) -> f64 {
+    samples.iter().sum::<f64>() / samples.len() as f64
+}
+
+/// Combine a baseline value with the RMS of the samples about it
+fn with_rms(value: f64, samples: &[f64]) -> Baseline {
+    let variance = samples
+        .iter()
+        .map(|sample| (sample - value).powi(2))
+        .sum::<f64>()
+        / samples.len() as f64;
+    Baseline {
+        value,
+        rms: variance.sqrt(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::Array1;
+
+    #[test]
+    fn mean_uses_first_buckets_by_index() {
+        let config = BaselineConfig {
+            n_buckets: 4,
+            ..Default::default()
+        };
+        // Buckets 1 and 3 were read in the baseline region, the signal after it must not be used
+        let trace = Array1::from_vec(vec![0, 10, 0, 20, 500, 500]);
+        let read = [false, true, false, true, true, true];
+        let baseline = estimate_baseline(&trace.view(), &read, &config).unwrap();
+        assert_eq!(baseline.value, 15.0);
+
+        let late = Array1::from_vec(vec![0, 0, 0, 0, 500, 500]);
+        let read = [false, false, false, false, true, true];
+        assert!(estimate_baseline(&late.view(), &read, &config).is_none());
+    }
+
+    #[test]
+    fn read_zero_samples_are_used() {
+        let config = BaselineConfig {
+            n_buckets: 4,
+            ..Default::default()
+        };
+        // A read sample of 0 (e.g. after pedestal subtraction) is part of the baseline
+        let trace = Array1::from_vec(vec![0, 10, 0, 20, 500, 500]);
+        let read = [true; 6];
+        let baseline = estimate_baseline(&trace.view(), &read, &config).unwrap();
+        assert_eq!(baseline.value, 7.5);
+    }
+}
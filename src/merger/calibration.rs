@@ -0,0 +1,99 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use fxhash::FxHashMap;
+
+use super::error::CalibrationError;
+use super::pad_map::HardwareID;
+
+/// # PadGain
+/// The gain calibration of a single pad. A calibrated sample is gain * sample + offset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PadGain {
+    pub gain: f64,
+    pub offset: f64,
+}
+
+impl Default for PadGain {
+    fn default() -> Self {
+        PadGain {
+            gain: 1.0,
+            offset: 0.0,
+        }
+    }
+}
+
+impl PadGain {
+    pub fn apply(&self, sample: f64) -> f64 {
+        self.gain * sample + self.offset
+    }
+}
+
+/// # GainCalibration
+/// GainCalibration holds the gain and offset of each pad, read from a CSV file. Each row either has 3 elements (pad number, gain,
+/// offset) or 6 elements (CoBo, AsAd, AGET, Channel, gain, offset), and both kinds of row can be mixed. A hardware address
+/// takes precedence over a pad number. Pads which are not in the file are left uncalibrated (gain 1, offset 0).
+#[derive(Debug, Clone, Default)]
+pub struct GainCalibration {
+    path: PathBuf,
+    by_pad: FxHashMap<usize, PadGain>,
+    by_address: FxHashMap<(usize, usize, usize, usize), PadGain>,
+}
+
+impl GainCalibration {
+    /// Read the calibration from the CSV file at the given path
+    pub fn new(path: &Path) -> Result<Self, CalibrationError> {
+        let mut file = File::open(path)?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+
+        let mut calibration = GainCalibration {
+            path: path.to_path_buf(),
+            ..Default::default()
+        };
+        for line in contents.lines() {
+            let entries: Vec<&str> = line.split_terminator(',').collect();
+            match entries.len() {
+                3 => {
+                    calibration.by_pad.insert(
+                        entries[0].parse()?,
+                        PadGain {
+                            gain: entries[1].parse()?,
+                            offset: entries[2].parse()?,
+                        },
+                    );
+                }
+                6 => {
+                    calibration.by_address.insert(
+                        (
+                            entries[0].parse()?,
+                            entries[1].parse()?,
+                            entries[2].parse()?,
+                            entries[3].parse()?,
+                        ),
+                        PadGain {
+                            gain: entries[4].parse()?,
+                            offset: entries[5].parse()?,
+                        },
+                    );
+                }
+                _ => return Err(CalibrationError::BadFileFormat),
+            }
+        }
+        Ok(calibration)
+    }
+
+    /// Get the calibration of a pad. Pads which are not in the calibration get the identity calibration.
+    pub fn get_gain(&self, hw_id: &HardwareID) -> PadGain {
+        let address = (hw_id.cobo_id, hw_id.asad_id, hw_id.aget_id, hw_id.channel);
+        match self.by_address.get(&address) {
+            Some(gain) => *gain,
+            None => self.by_pad.get(&hw_id.pad_id).copied().unwrap_or_default(),
+        }
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+}
@@ -0,0 +1,331 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use fxhash::FxHashMap;
+use ndarray::Array2;
+
+use super::completeness::board_bit;
+use super::config::{ChannelMaskConfig, Config, MaskAction};
+use super::error::{ChannelMaskError, ProcessorError};
+use super::event::Event;
+use super::event_builder::EventBuilder;
+use super::merger::Merger;
+use super::pad_map::PadMap;
+use super::pedestal::ChannelAddress;
+
+/// # ChannelStatus
+/// Why a channel is masked
+/// - Dead: the channel (almost) never has a trace
+/// - Hot: the channel has a trace in (almost) every event
+/// - Noisy: the samples of the channel have a large RMS
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelStatus {
+    Dead,
+    Hot,
+    Noisy,
+}
+
+impl ChannelStatus {
+    /// The name used in the mask file
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChannelStatus::Dead => "dead",
+            ChannelStatus::Hot => "hot",
+            ChannelStatus::Noisy => "noisy",
+        }
+    }
+
+    /// The code used in the channel_mask dataset of the output
+    pub fn code(&self) -> i16 {
+        match self {
+            ChannelStatus::Dead => 0,
+            ChannelStatus::Hot => 1,
+            ChannelStatus::Noisy => 2,
+        }
+    }
+
+    fn from_name(name: &str) -> Result<Self, ChannelMaskError> {
+        match name {
+            "dead" => Ok(ChannelStatus::Dead),
+            "hot" => Ok(ChannelStatus::Hot),
+            "noisy" => Ok(ChannelStatus::Noisy),
+            _ => Err(ChannelMaskError::BadFileFormat),
+        }
+    }
+}
+
+/// # ChannelMask
+/// A list of bad channels, made by survey_channels and stored in a CSV file. Each row is CoBo, AsAd, AGET, Channel, status,
+/// where the status is one of dead, hot, or noisy. The mask is applied to later runs through the PadMap, either by dropping
+/// the channels or by tagging them in the output (see MaskAction).
+#[derive(Debug, Clone, Default)]
+pub struct ChannelMask {
+    path: PathBuf,
+    channels: FxHashMap<ChannelAddress, ChannelStatus>,
+}
+
+impl ChannelMask {
+    /// Read a mask from the CSV file at the given path
+    pub fn read(path: &Path) -> Result<Self, ChannelMaskError> {
+        let mut file = File::open(path)?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+
+        let mut mask = ChannelMask {
+            path: path.to_path_buf(),
+            channels: FxHashMap::default(),
+        };
+        for line in contents.lines() {
+            let entries: Vec<&str> = line.split_terminator(',').map(|e| e.trim()).collect();
+            if entries.len() != 5 {
+                return Err(ChannelMaskError::BadFileFormat);
+            }
+            mask.channels.insert(
+                (
+                    entries[0].parse()?,
+                    entries[1].parse()?,
+                    entries[2].parse()?,
+                    entries[3].parse()?,
+                ),
+                ChannelStatus::from_name(entries[4])?,
+            );
+        }
+        Ok(mask)
+    }
+
+    /// Write the mask to a CSV file
+    pub fn write(&self, path: &Path) -> Result<(), ChannelMaskError> {
+        let mut file = File::create(path)?;
+        for (address, status) in self.sorted() {
+            writeln!(
+                file,
+                "{},{},{},{},{}",
+                address.0,
+                address.1,
+                address.2,
+                address.3,
+                status.name()
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Apply the mask to a PadMap. With MaskAction::Drop the masked channels are removed from the map, so their data is
+    /// skipped when events are built. With MaskAction::Tag the map is left as is.
+    pub fn apply(&self, pad_map: &mut PadMap, action: MaskAction) {
+        if action == MaskAction::Tag {
+            return;
+        }
+        let mut dropped = 0;
+        for address in self.channels.keys() {
+            if pad_map
+                .remove_hardware_id(&address.0, &address.1, &address.2, &address.3)
+                .is_some()
+            {
+                dropped += 1;
+            }
+        }
+        log::info!(
+            "Dropped {} masked channels from the pad map using {}",
+            dropped,
+            self.path.display()
+        );
+    }
+
+    /// The mask as a matrix, one row per channel: CoBo, AsAd, AGET, Channel, status code
+    pub fn get_mask_matrix(&self) -> Array2<i16> {
+        let mut matrix = Array2::<i16>::zeros([self.channels.len(), 5]);
+        for (row, (address, status)) in self.sorted().into_iter().enumerate() {
+            matrix[[row, 0]] = address.0 as i16;
+            matrix[[row, 1]] = address.1 as i16;
+            matrix[[row, 2]] = address.2 as i16;
+            matrix[[row, 3]] = address.3 as i16;
+            matrix[[row, 4]] = status.code();
+        }
+        matrix
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn len(&self) -> usize {
+        self.channels.len()
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty()
+    }
+
+    /// The channels sorted by address, so that files are easy to read by eye
+    fn sorted(&self) -> Vec<(&ChannelAddress, &ChannelStatus)> {
+        let mut sorted: Vec<(&ChannelAddress, &ChannelStatus)> = self.channels.iter().collect();
+        sorted.sort_by_key(|(address, _)| **address);
+        sorted
+    }
+}
+
+/// Read the pad map of the config and apply the channel mask of the config, if there is one.
+/// Returns the PadMap and the ChannelMask.
+pub fn load_masked_pad_map(
+    config: &Config,
+) -> Result<(PadMap, Option<ChannelMask>), ProcessorError> {
+    let mut pad_map = PadMap::new(&config.pad_map_path)?;
+    let mask = match &config.channel_mask.path {
+        Some(path) => {
+            let mask = ChannelMask::read(path)?;
+            mask.apply(&mut pad_map, config.channel_mask.action);
+            Some(mask)
+        }
+        None => None,
+    };
+    Ok((pad_map, mask))
+}
+
+/// # ChannelStats
+/// The occupancy and sample statistics of a single channel over a run. Empty buckets are not counted.
+#[derive(Debug, Clone, Default)]
+struct ChannelStats {
+    n_traces: u64,
+    n_samples: f64,
+    sum: f64,
+    sum_sq: f64,
+}
+
+impl ChannelStats {
+    fn mean(&self) -> f64 {
+        if self.n_samples > 0.0 {
+            self.sum / self.n_samples
+        } else {
+            0.0
+        }
+    }
+
+    fn rms(&self) -> f64 {
+        if self.n_samples > 0.0 {
+            let mean = self.mean();
+            (self.sum_sq / self.n_samples - mean * mean).max(0.0).sqrt()
+        } else {
+            0.0
+        }
+    }
+}
+
+/// # ChannelSurvey
+/// Accumulates the ChannelStats of every channel over the events of a run
+#[derive(Debug, Default)]
+struct ChannelSurvey {
+    n_events: u64,
+    stats: FxHashMap<ChannelAddress, ChannelStats>,
+}
+
+impl ChannelSurvey {
+    fn add_event(&mut self, event: &Event) {
+        for (hw_id, trace) in event.iter_traces() {
+            let stats = self
+                .stats
+                .entry((
+                    hw_id.cobo_id as u8,
+                    hw_id.asad_id as u8,
+                    hw_id.aget_id as u8,
+                    hw_id.channel as u8,
+                ))
+                .or_default();
+            stats.n_traces += 1;
+            for sample in trace.iter().filter(|sample| **sample != 0) {
+                let value = *sample as f64;
+                stats.n_samples += 1.0;
+                stats.sum += value;
+                stats.sum_sq += value * value;
+            }
+        }
+        self.n_events += 1;
+    }
+
+    /// Flag the bad channels. Every channel of the pad map on the expected boards (see Merger::get_expected_boards) is checked,
+    /// so channels which never had a trace are dead. Channels on boards which were excluded or had no data are not checked.
+    fn into_mask(
+        self,
+        pad_map: &PadMap,
+        expected_boards: u64,
+        config: &ChannelMaskConfig,
+    ) -> ChannelMask {
+        let mut mask = ChannelMask::default();
+        if self.n_events == 0 {
+            return mask;
+        }
+        let empty = ChannelStats::default();
+        for hw_id in pad_map.iter().filter(|hw_id| {
+            board_bit(hw_id.cobo_id as u8, hw_id.asad_id as u8) & expected_boards != 0
+        }) {
+            let address = (
+                hw_id.cobo_id as u8,
+                hw_id.asad_id as u8,
+                hw_id.aget_id as u8,
+                hw_id.channel as u8,
+            );
+            let stats = self.stats.get(&address).unwrap_or(&empty);
+            let occupancy = stats.n_traces as f64 / self.n_events as f64;
+            let status = if occupancy < config.min_occupancy {
+                Some(ChannelStatus::Dead)
+            } else if occupancy > config.max_occupancy {
+                Some(ChannelStatus::Hot)
+            } else if stats.rms() > config.max_rms {
+                Some(ChannelStatus::Noisy)
+            } else {
+                None
+            };
+            if let Some(status) = status {
+                log::debug!(
+                    "Channel {:?} is {}: occupancy {}, mean {}, rms {}",
+                    address,
+                    status.name(),
+                    occupancy,
+                    stats.mean(),
+                    stats.rms()
+                );
+                mask.channels.insert(address, status);
+            }
+        }
+        mask
+    }
+}
+
+/// Survey the channels of a run: merge the raw traces of every event, find the occupancy, mean and RMS of each channel, and
+/// flag the dead, hot, and noisy channels using the thresholds of the config. The mask is written to the output path. No
+/// trace processing is applied, and the mask of the config is not used. Returns the number of masked channels.
+pub fn survey_channels(
+    config: &Config,
+    run_number: i32,
+    output_path: &Path,
+) -> Result<usize, ProcessorError> {
+    let pad_map = PadMap::new(&config.pad_map_path)?;
+    let mut merger = Merger::new(config, run_number)?;
+    let mut evb = EventBuilder::new(pad_map.clone(), config);
+    let mut survey = ChannelSurvey::default();
+
+    log::info!("Surveying the channels of run {}...", run_number);
+    while let Some(frame) = merger.get_next_frame()? {
+        if let Some(event) = evb.append_frame(frame)? {
+            survey.add_event(&event);
+        }
+    }
+    for event in evb.flush_events() {
+        survey.add_event(&event);
+    }
+    evb.get_stats().log_summary();
+
+    let n_events = survey.n_events;
+    let mut mask = survey.into_mask(&pad_map, merger.get_expected_boards(), &config.channel_mask);
+    mask.path = output_path.to_path_buf();
+    mask.write(output_path)?;
+    log::info!(
+        "Masked {} channels using {} events, written to {}",
+        mask.len(),
+        n_events,
+        output_path.display()
+    );
+    Ok(mask.len())
+}
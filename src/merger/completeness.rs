@@ -0,0 +1,89 @@
+use ndarray::Array2;
+
+use super::constants::{NUMBER_OF_ASADS, NUMBER_OF_COBOS};
+use super::event::Event;
+
+/// The bit representing a given CoBo/AsAd in the contributor mask of an Event. The bit index is cobo * NUMBER_OF_ASADS + asad.
+/// Returns 0 for hardware outside of the expected range.
+pub fn board_bit(cobo_id: u8, asad_id: u8) -> u64 {
+    if cobo_id < NUMBER_OF_COBOS && asad_id < NUMBER_OF_ASADS {
+        1 << (cobo_id as u64 * NUMBER_OF_ASADS as u64 + asad_id as u64)
+    } else {
+        0
+    }
+}
+
+/// # CompletenessCounter
+/// Tracks over a run how often each CoBo/AsAd which was expected (had data files) did not contribute a frame to an event.
+/// Dead or lagging AsAds show up as large counts in the missing matrix.
+#[derive(Debug, Clone)]
+pub struct CompletenessCounter {
+    expected_boards: u64,
+    missing_counts: Array2<u64>, // indexed by [cobo, asad]
+    incomplete_events: u64,
+    total_events: u64,
+}
+
+impl CompletenessCounter {
+    /// Create a new counter. The expected boards are given as a contributor mask (see board_bit)
+    pub fn new(expected_boards: u64) -> Self {
+        CompletenessCounter {
+            expected_boards,
+            missing_counts: Array2::<u64>::zeros([
+                NUMBER_OF_COBOS as usize,
+                NUMBER_OF_ASADS as usize,
+            ]),
+            incomplete_events: 0,
+            total_events: 0,
+        }
+    }
+
+    /// Count the boards missing from an event
+    pub fn record(&mut self, event: &Event) {
+        self.total_events += 1;
+        let missing = self.expected_boards & !event.contributors;
+        if missing == 0 {
+            return;
+        }
+        self.incomplete_events += 1;
+        for cobo in 0..NUMBER_OF_COBOS {
+            for asad in 0..NUMBER_OF_ASADS {
+                if missing & board_bit(cobo, asad) != 0 {
+                    self.missing_counts[[cobo as usize, asad as usize]] += 1;
+                }
+            }
+        }
+    }
+
+    /// Log every board which was missing from at least one event
+    pub fn log_summary(&self) {
+        log::info!(
+            "{} of {} events were missing at least one CoBo/AsAd",
+            self.incomplete_events,
+            self.total_events
+        );
+        for ((cobo, asad), count) in self.missing_counts.indexed_iter() {
+            if *count != 0 {
+                log::warn!(
+                    "CoBo {} AsAd {} was missing from {} events",
+                    cobo,
+                    asad,
+                    count
+                );
+            }
+        }
+    }
+
+    pub fn get_missing_counts(&self) -> &Array2<u64> {
+        &self.missing_counts
+    }
+
+    /// Summary array of [total events, incomplete events, expected board mask]
+    pub fn get_summary_array(&self) -> [u64; 3] {
+        [
+            self.total_events,
+            self.incomplete_events,
+            self.expected_boards,
+        ]
+    }
+}
@@ -1,7 +1,405 @@
 use serde_derive::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 
+use super::constants::{MAXIMUM_SAMPLE, NUMBER_OF_TIME_BUCKETS};
 use super::error::ConfigError;
+use super::event::Event;
+
+/// # MergeMode
+/// How frames are ordered by the Merger and grouped into events by the EventBuilder.
+/// - EventId: frames are ordered by EventID and frames with the same EventID form an event
+/// - Timestamp: frames are ordered by timestamp and frames whose timestamps agree within the tolerance form an event. Used when
+/// the CoBo event counters desynchronize.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum MergeMode {
+    #[default]
+    EventId = 0,
+    Timestamp = 1,
+}
+
+/// # DuplicatePolicy
+/// What the EventBuilder does when an event receives a second frame from the same CoBo/AsAd (i.e. from a replicated file or a
+/// DAQ retransmit).
+/// - KeepFirst: the duplicate frame is dropped
+/// - KeepLast: the duplicate frame replaces the earlier one
+/// - Error: processing stops with an error
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum DuplicatePolicy {
+    #[default]
+    KeepFirst,
+    KeepLast,
+    Error,
+}
+
+/// # TraceOrder
+/// The order in which the traces of an event are written. Either way the output of a run is reproducible.
+/// - HardwareAddress: sorted by CoBo, AsAd, AGET, then Channel
+/// - Pad: sorted by pad number
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum TraceOrder {
+    #[default]
+    HardwareAddress,
+    Pad,
+}
+
+/// # BoardID
+/// Identifies a single AsAd on a CoBo
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BoardID {
+    pub cobo: u8,
+    pub asad: u8,
+}
+
+/// # EventWindow
+/// Restricts the processing of a run to a range of event ids, a window of GET timestamps (10 ns units), and/or a maximum
+/// number of events. Limits which are not set are not applied. Reading stops as soon as the window has been passed.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EventWindow {
+    pub first_event_id: Option<u32>,
+    pub last_event_id: Option<u32>,
+    pub max_events: Option<u64>,
+    pub first_timestamp: Option<u64>,
+    pub last_timestamp: Option<u64>,
+}
+
+impl EventWindow {
+    /// Check if an event is inside the window
+    pub fn contains(&self, event: &Event) -> bool {
+        self.first_event_id.is_none_or(|id| event.event_id >= id)
+            && self.last_event_id.is_none_or(|id| event.event_id <= id)
+            && self.first_timestamp.is_none_or(|ts| event.timestamp >= ts)
+            && self.last_timestamp.is_none_or(|ts| event.timestamp <= ts)
+    }
+
+    /// Check if the window has been passed, either by this event or because the maximum number of events was written
+    pub fn is_passed(&self, event: &Event, events_written: &u64) -> bool {
+        self.last_event_id.is_some_and(|id| event.event_id > id)
+            || self.last_timestamp.is_some_and(|ts| event.timestamp > ts)
+            || self.max_events.is_some_and(|max| *events_written >= max)
+    }
+
+    /// The window as an array of [first event id, last event id, max events, first timestamp, last timestamp]. Limits
+    /// which are not set are given as -1.
+    pub fn get_window_array(&self) -> [i64; 5] {
+        [
+            self.first_event_id.map_or(-1, |id| id as i64),
+            self.last_event_id.map_or(-1, |id| id as i64),
+            self.max_events.map_or(-1, |max| max as i64),
+            self.first_timestamp.map_or(-1, |ts| ts as i64),
+            self.last_timestamp.map_or(-1, |ts| ts as i64),
+        ]
+    }
+}
+
+/// # FilterConfig
+/// Criteria an event must pass to be written. Criteria which are not set are not applied. Events are checked before any trace
+/// processing, so the criteria use the raw traces.
+/// - min_pads: the minimum number of traces in the event (including FPN channels)
+/// - required_cobos: CoBos which must all have contributed a frame to the event
+/// - coincidence_mask: bits which must all be set in the V977 coincidence register of the matching FRIBDAQ physics event
+/// - min_total_charge: the minimum sum of all raw ADC samples of all traces in the event, baselines included
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FilterConfig {
+    pub min_pads: Option<usize>,
+    pub required_cobos: Vec<u8>,
+    pub coincidence_mask: Option<u16>,
+    pub min_total_charge: Option<i64>,
+}
+
+/// # BaselineMethod
+/// How the baseline of a trace is estimated. Empty (zero) buckets are never used.
+/// - Mean: the mean of the first n_buckets buckets. Traces with no samples in these buckets have no baseline
+/// - Median: the median of the first n_buckets buckets. Traces with no samples in these buckets have no baseline
+/// - Percentile: the given percentile of every bucket in the trace. Robust against signals anywhere in the trace
+/// - Iterative: the mean of every bucket, repeatedly excluding buckets more than n_sigma RMS away from the mean until the
+/// estimate stops changing (or max_iterations is reached). Excludes the signal without knowing where it is
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum BaselineMethod {
+    #[default]
+    Mean,
+    Median,
+    Percentile,
+    Iterative,
+}
+
+/// # RunBaselineMethod
+/// Overrides the baseline method for a single run
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RunBaselineMethod {
+    pub run: i32,
+    pub method: BaselineMethod,
+}
+
+/// # BaselineConfig
+/// Controls the per-trace baseline subtraction stage (see Event::subtract_baselines). Only the settings of the chosen
+/// method are used. The method can be overridden for individual runs with run_methods.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BaselineConfig {
+    pub enabled: bool,
+    pub method: BaselineMethod,
+    pub n_buckets: usize,      // Mean and Median
+    pub percentile: f64,       // Percentile, 0-100
+    pub n_sigma: f64,          // Iterative
+    pub max_iterations: usize, // Iterative
+    pub run_methods: Vec<RunBaselineMethod>,
+}
+
+impl Default for BaselineConfig {
+    fn default() -> Self {
+        BaselineConfig {
+            enabled: false,
+            method: BaselineMethod::Mean,
+            n_buckets: 20,
+            percentile: 20.0,
+            n_sigma: 3.0,
+            max_iterations: 10,
+            run_methods: Vec::new(),
+        }
+    }
+}
+
+impl BaselineConfig {
+    /// The settings to use for a given run, applying any override of the method
+    pub fn for_run(&self, run_number: i32) -> Self {
+        let mut settings = self.clone();
+        if let Some(run_method) = self.run_methods.iter().find(|rm| rm.run == run_number) {
+            settings.method = run_method.method;
+        }
+        settings
+    }
+}
+
+/// # SaturationConfig
+/// Controls the saturation detection stage (see Event::find_saturation). Runs on the raw samples.
+/// - enabled: count the saturated buckets of each trace
+/// - ceiling: samples at or above the ceiling are saturated
+/// - floor: samples at or below the floor are clipped. Buckets which were not read are stored as 0, so a sample of 0 is never
+/// counted
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SaturationConfig {
+    pub enabled: bool,
+    pub ceiling: i16,
+    pub floor: i16,
+}
+
+impl Default for SaturationConfig {
+    fn default() -> Self {
+        SaturationConfig {
+            enabled: false,
+            ceiling: MAXIMUM_SAMPLE,
+            floor: 1,
+        }
+    }
+}
+
+/// # MaskAction
+/// What is done with the channels listed in a channel mask file
+/// - Drop: the channels are removed from the pad map, so their data is never read
+/// - Tag: the channels are kept, and the mask is written to the output for the analysis to use
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum MaskAction {
+    #[default]
+    Drop,
+    Tag,
+}
+
+/// # ChannelMaskConfig
+/// Controls the channel survey (see channel_mask::survey_channels) and the use of its mask file.
+/// - path: a mask file to apply to the runs being processed
+/// - action: what to do with the masked channels
+/// - min_occupancy: channels with a trace in a smaller fraction of events are dead
+/// - max_occupancy: channels with a trace in a larger fraction of events are hot
+/// - max_rms: channels whose samples have a larger RMS are noisy
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChannelMaskConfig {
+    pub path: Option<PathBuf>,
+    pub action: MaskAction,
+    pub min_occupancy: f64,
+    pub max_occupancy: f64,
+    pub max_rms: f64,
+}
+
+impl Default for ChannelMaskConfig {
+    fn default() -> Self {
+        ChannelMaskConfig {
+            path: None,
+            action: MaskAction::Drop,
+            min_occupancy: 0.001,
+            max_occupancy: 0.9,
+            max_rms: 50.0,
+        }
+    }
+}
+
+/// # FpnConfig
+/// Controls the fixed pattern noise subtraction stage (see Event::subtract_fixed_pattern_noise).
+/// - enabled: subtract the FPN from the traces and remove the FPN channels
+/// - keep_fpn_traces: write the removed FPN traces to a separate dataset for each event
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FpnConfig {
+    pub enabled: bool,
+    pub keep_fpn_traces: bool,
+}
+
+/// # HitConfig
+/// Controls the hit finding stage (see hits::find_hits).
+/// - enabled: find the hits in each trace and write them to a hit table for each event
+/// - threshold: the minimum (smoothed) sample of a hit
+/// - smoothing: the width of the moving average applied before finding hits, in buckets (0 or 1 for none)
+/// - multi_peak: split regions above the threshold with more than one peak into separate hits
+/// - min_separation: the minimum distance between two peaks in a region, in buckets
+/// - write_traces: also write the traces. If false only the hit tables are written
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HitConfig {
+    pub enabled: bool,
+    pub threshold: f64,
+    pub smoothing: usize,
+    pub multi_peak: bool,
+    pub min_separation: usize,
+    pub write_traces: bool,
+}
+
+impl Default for HitConfig {
+    fn default() -> Self {
+        HitConfig {
+            enabled: false,
+            threshold: 50.0,
+            smoothing: 0,
+            multi_peak: false,
+            min_separation: 5,
+            write_traces: true,
+        }
+    }
+}
+
+/// # DeconvolutionConfig
+/// Controls the deconvolution stage (see deconvolution::deconvolve).
+/// - enabled: deconvolve each trace with the AGET response
+/// - response_path: a measured response (see deconvolution::ResponseFunction). If not given, the analytic response is used
+/// - peaking_time: the AGET peaking time in ns, for the analytic response. Uses the sampling frequency of the SamplingConfig
+/// - iterations: the number of Richardson-Lucy iterations
+/// - write_traces: write the deconvolved traces
+/// - find_hits: find hits in the deconvolved traces, with the options of the hit finding stage
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeconvolutionConfig {
+    pub enabled: bool,
+    pub response_path: Option<PathBuf>,
+    pub peaking_time: f64,
+    pub iterations: usize,
+    pub write_traces: bool,
+    pub find_hits: bool,
+}
+
+impl Default for DeconvolutionConfig {
+    fn default() -> Self {
+        DeconvolutionConfig {
+            enabled: false,
+            response_path: None,
+            peaking_time: 502.0,
+            iterations: 20,
+            write_traces: true,
+            find_hits: false,
+        }
+    }
+}
+
+/// # PointCloudConfig
+/// Controls the conversion of hits to 3D points (see Event::build_point_cloud). Needs the hit finding stage and a pad geometry.
+/// - enabled: write a point cloud for each event
+/// - drift_velocity: electron drift velocity in mm/us
+/// - trigger_offset: the time bucket of the trigger, where z = 0
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PointCloudConfig {
+    pub enabled: bool,
+    pub drift_velocity: f64,
+    pub trigger_offset: f64,
+}
+
+impl Default for PointCloudConfig {
+    fn default() -> Self {
+        PointCloudConfig {
+            enabled: false,
+            drift_velocity: 0.0,
+            trigger_offset: 0.0,
+        }
+    }
+}
+
+impl PointCloudConfig {
+    /// Convert a time bucket to z in mm, given the GET sampling frequency in MHz
+    pub fn get_z(&self, time_bucket: f64, sampling_frequency: f64) -> f64 {
+        (time_bucket - self.trigger_offset) / sampling_frequency * self.drift_velocity
+    }
+}
+
+/// # SamplingConfig
+/// Controls the time buckets written for each trace (see Event::resample). Applied after every other stage, so hits, point
+/// clouds, and saturation use the full traces.
+/// - first_bucket, last_bucket: the (inclusive) range of buckets to keep
+/// - rebin: the number of buckets averaged into each written bucket. Buckets left over at the end of the range are dropped
+/// - sampling_frequency: GET sampling frequency in MHz, before rebinning. Also used by the point cloud and deconvolution stages
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SamplingConfig {
+    pub first_bucket: usize,
+    pub last_bucket: usize,
+    pub rebin: usize,
+    pub sampling_frequency: f64,
+}
+
+impl Default for SamplingConfig {
+    fn default() -> Self {
+        SamplingConfig {
+            first_bucket: 0,
+            last_bucket: NUMBER_OF_TIME_BUCKETS as usize - 1,
+            rebin: 1,
+            sampling_frequency: 6.25,
+        }
+    }
+}
+
+impl SamplingConfig {
+    /// Check if the config leaves the traces as they are
+    pub fn is_identity(&self) -> bool {
+        let default = SamplingConfig::default();
+        self.first_bucket == default.first_bucket
+            && self.last_bucket == default.last_bucket
+            && self.rebin == default.rebin
+    }
+
+    /// The number of buckets written for each trace
+    pub fn get_n_buckets(&self) -> usize {
+        (self.last_bucket + 1).saturating_sub(self.first_bucket) / self.rebin.max(1)
+    }
+
+    /// Check that the range is within a trace, at least one bucket is written, and the sampling frequency is positive
+    pub fn is_valid(&self) -> bool {
+        self.last_bucket < NUMBER_OF_TIME_BUCKETS as usize
+            && self.rebin != 0
+            && self.get_n_buckets() != 0
+            && self.sampling_frequency > 0.0
+    }
+}
+
+/// # SparseConfig
+/// Controls how the traces are written (see sparse::SparseTraces).
+/// - enabled: write the traces as runs of buckets instead of a dense matrix
+/// - threshold: if given, only buckets with samples above the threshold are kept. Otherwise every bucket which was read is kept
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SparseConfig {
+    pub enabled: bool,
+    pub threshold: Option<i16>,
+}
 
 /// # Config
 /// Structure representing the application configuration. Contains pathing and run information
@@ -12,10 +410,56 @@ pub struct Config {
     pub evt_path: PathBuf,
     pub hdf_path: PathBuf,
     pub pad_map_path: PathBuf,
+    #[serde(default)]
+    pub pad_geometry_path: Option<PathBuf>, // pad positions, see pad_geometry::PadGeometry
+    #[serde(default)]
+    pub channel_mask: ChannelMaskConfig,
     pub first_run_number: i32,
     pub last_run_number: i32,
     pub online: bool,
     pub experiment: String,
+    #[serde(default)]
+    pub merge_mode: MergeMode,
+    #[serde(default)]
+    pub timestamp_tolerance: u64, // in GET timestamp units (10 ns), only used by MergeMode::Timestamp
+    #[serde(default)]
+    pub reorder_window: usize, // number of extra events the EventBuilder keeps open for late frames
+    #[serde(default)]
+    pub duplicate_policy: DuplicatePolicy,
+    #[serde(default)]
+    pub trace_order: TraceOrder,
+    #[serde(default)]
+    pub cobo_include: Vec<u8>, // if not empty, only these CoBos are merged
+    #[serde(default)]
+    pub cobo_exclude: Vec<u8>,
+    #[serde(default)]
+    pub asad_include: Vec<BoardID>, // if not empty, only these AsAds are merged
+    #[serde(default)]
+    pub asad_exclude: Vec<BoardID>,
+    #[serde(default)]
+    pub event_window: EventWindow,
+    #[serde(default)]
+    pub event_filter: FilterConfig,
+    #[serde(default)]
+    pub pedestal_path: Option<PathBuf>, // pedestal file to subtract from the traces, see pedestal::PedestalTable
+    #[serde(default)]
+    pub calibration_path: Option<PathBuf>, // per-pad gains, see calibration::GainCalibration
+    #[serde(default)]
+    pub saturation: SaturationConfig,
+    #[serde(default)]
+    pub fpn: FpnConfig,
+    #[serde(default)]
+    pub baseline: BaselineConfig,
+    #[serde(default)]
+    pub sparse: SparseConfig,
+    #[serde(default)]
+    pub sampling: SamplingConfig,
+    #[serde(default)]
+    pub hits: HitConfig,
+    #[serde(default)]
+    pub point_cloud: PointCloudConfig,
+    #[serde(default)]
+    pub deconvolution: DeconvolutionConfig,
 }
 
 impl Config {
@@ -26,10 +470,33 @@ impl Config {
             evt_path: PathBuf::from("None"),
             hdf_path: PathBuf::from("None"),
             pad_map_path: PathBuf::from("None"),
+            pad_geometry_path: None,
+            channel_mask: ChannelMaskConfig::default(),
             first_run_number: 0,
             last_run_number: 0,
             online: false,
             experiment: String::from(""),
+            merge_mode: MergeMode::EventId,
+            timestamp_tolerance: 0,
+            reorder_window: 0,
+            duplicate_policy: DuplicatePolicy::KeepFirst,
+            trace_order: TraceOrder::HardwareAddress,
+            cobo_include: Vec::new(),
+            cobo_exclude: Vec::new(),
+            asad_include: Vec::new(),
+            asad_exclude: Vec::new(),
+            event_window: EventWindow::default(),
+            event_filter: FilterConfig::default(),
+            pedestal_path: None,
+            calibration_path: None,
+            saturation: SaturationConfig::default(),
+            fpn: FpnConfig::default(),
+            baseline: BaselineConfig::default(),
+            sparse: SparseConfig::default(),
+            sampling: SamplingConfig::default(),
+            hits: HitConfig::default(),
+            point_cloud: PointCloudConfig::default(),
+            deconvolution: DeconvolutionConfig::default(),
         }
     }
 
@@ -55,6 +522,20 @@ impl Config {
         }
     }
 
+    /// Check if a CoBo should be merged according to the include/exclude lists
+    pub fn is_cobo_selected(&self, cobo: u8) -> bool {
+        (self.cobo_include.is_empty() || self.cobo_include.contains(&cobo))
+            && !self.cobo_exclude.contains(&cobo)
+    }
+
+    /// Check if an AsAd should be merged according to the include/exclude lists. The CoBo of the AsAd must also be selected.
+    pub fn is_asad_selected(&self, cobo: u8, asad: u8) -> bool {
+        let board = BoardID { cobo, asad };
+        self.is_cobo_selected(cobo)
+            && (self.asad_include.is_empty() || self.asad_include.contains(&board))
+            && !self.asad_exclude.contains(&board)
+    }
+
     /// Construct the run directory
     pub fn get_run_directory(&self, run_number: i32, cobo: &u8) -> Result<PathBuf, ConfigError> {
         let mut run_dir: PathBuf = self.graw_path.join(self.get_run_str(run_number));
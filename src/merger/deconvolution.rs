@@ -0,0 +1,139 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use ndarray::{Array1, ArrayView1};
+
+use super::calibration::PadGain;
+use super::constants::NUMBER_OF_TIME_BUCKETS;
+use super::error::DeconvolutionError;
+use super::hits::calibrate;
+
+/// The analytic response is cut off once it falls below this fraction of its peak
+const RESPONSE_CUTOFF: f64 = 1.0e-4;
+
+/// # ResponseFunction
+/// The response of the AGET shaper to a unit charge, sampled at the time buckets and normalized to a sum of 1. The first value
+/// is the bucket at which the charge arrives.
+///
+/// ## Sources
+/// - A response file: a CSV file of sampled values, one or more per line, i.e. the averaged (baseline subtracted) trace of a
+/// pulser run. Values before the first non-zero value are dropped, so the response starts when the pulse arrives.
+/// - Analytic: the CR-RC^4 semi-gaussian response (t / tau)^4 exp(-t / tau), with tau = peaking time / 4 so that the response
+/// peaks at the peaking time.
+#[derive(Debug, Clone)]
+pub struct ResponseFunction {
+    path: Option<PathBuf>,
+    values: Array1<f64>,
+}
+
+impl ResponseFunction {
+    /// Read a measured response from the CSV file at the given path
+    pub fn read(path: &Path) -> Result<Self, DeconvolutionError> {
+        let mut file = File::open(path)?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+
+        let mut values: Vec<f64> = Vec::new();
+        for line in contents.lines() {
+            for entry in line.split_terminator(',') {
+                values.push(entry.trim().parse()?);
+            }
+        }
+        let first = values
+            .iter()
+            .position(|value| *value != 0.0)
+            .ok_or(DeconvolutionError::EmptyResponse)?;
+        let mut response = Self::normalized(values.split_off(first))?;
+        response.path = Some(path.to_path_buf());
+        Ok(response)
+    }
+
+    /// Make the analytic response for a peaking time in ns and a sampling frequency in MHz
+    pub fn analytic(
+        peaking_time: f64,
+        sampling_frequency: f64,
+    ) -> Result<Self, DeconvolutionError> {
+        let tau = peaking_time / 4.0 * sampling_frequency / 1000.0; // in buckets
+        let shape = |bucket: f64| (bucket / tau).powi(4) * (-bucket / tau).exp();
+        let peak = shape(4.0 * tau);
+        let mut values: Vec<f64> = Vec::new();
+        for bucket in 0..(NUMBER_OF_TIME_BUCKETS as usize) {
+            let value = shape(bucket as f64);
+            if bucket as f64 > 4.0 * tau && value < RESPONSE_CUTOFF * peak {
+                break;
+            }
+            values.push(value);
+        }
+        Self::normalized(values)
+    }
+
+    /// The sampled values of the response
+    pub fn get_values(&self) -> &Array1<f64> {
+        &self.values
+    }
+
+    /// The response file, if the response was measured
+    pub fn get_path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Normalize the values to a sum of 1
+    fn normalized(values: Vec<f64>) -> Result<Self, DeconvolutionError> {
+        let sum: f64 = values.iter().sum();
+        if values.is_empty() || sum <= 0.0 || !sum.is_finite() {
+            return Err(DeconvolutionError::EmptyResponse);
+        }
+        Ok(ResponseFunction {
+            path: None,
+            values: Array1::from_vec(values) / sum,
+        })
+    }
+}
+
+/// Deconvolve a trace with the response function, using the given number of Richardson-Lucy iterations. The trace is calibrated
+/// with the gain of the pad, and negative samples (noise below the baseline) are taken as 0, so the trace should already have
+/// its baseline removed. The result has the same length as the trace, and approximately keeps its integral.
+///
+/// Each iteration costs (trace length) x (response length) operations, so long responses and many iterations are slow.
+pub fn deconvolve(
+    trace: &ArrayView1<i16>,
+    gain: &PadGain,
+    response: &ResponseFunction,
+    iterations: usize,
+) -> Array1<f64> {
+    let observed = calibrate(trace, gain).mapv(|value| value.max(0.0));
+    let total: f64 = observed.sum();
+    if total <= 0.0 {
+        return observed;
+    }
+    let length = observed.len();
+    let kernel = &response.values;
+
+    // Start from a flat estimate with the same integral
+    let mut estimate = Array1::<f64>::from_elem(length, total / length as f64);
+    let mut ratio = Array1::<f64>::zeros(length);
+    for _ in 0..iterations {
+        // ratio = observed / (estimate convolved with the response)
+        for idx in 0..length {
+            let mut blurred = 0.0;
+            for (shift, weight) in kernel.iter().enumerate().take(idx + 1) {
+                blurred += weight * estimate[idx - shift];
+            }
+            ratio[idx] = if blurred > f64::EPSILON {
+                observed[idx] / blurred
+            } else {
+                0.0
+            };
+        }
+        // estimate *= ratio correlated with the response
+        for idx in 0..length {
+            let mut correction = 0.0;
+            for (shift, weight) in kernel.iter().enumerate().take(length - idx) {
+                correction += weight * ratio[idx + shift];
+            }
+            estimate[idx] *= correction;
+        }
+    }
+    estimate
+}
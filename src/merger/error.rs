@@ -243,6 +243,76 @@ impl Display for EvtStackError {
 
 impl Error for EvtStackError {}
 
+/*
+   FrameIndex errors
+*/
+
+#[derive(Debug)]
+pub enum FrameIndexError {
+    IOError(std::io::Error),
+    FileError(GrawFileError),
+    BadFileFormat(PathBuf),
+}
+
+impl From<std::io::Error> for FrameIndexError {
+    fn from(value: std::io::Error) -> Self {
+        Self::IOError(value)
+    }
+}
+
+impl From<GrawFileError> for FrameIndexError {
+    fn from(value: GrawFileError) -> Self {
+        Self::FileError(value)
+    }
+}
+
+impl Display for FrameIndexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IOError(e) => write!(f, "FrameIndex recieved an io error: {}", e),
+            Self::FileError(e) => write!(f, "FrameIndex recieved a file error: {}", e),
+            Self::BadFileFormat(path) => write!(
+                f,
+                "FrameIndex found a bad file format while reading the index file {}!",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl Error for FrameIndexError {}
+
+/*
+   Pedestal errors
+*/
+
+#[derive(Debug)]
+pub enum PedestalError {
+    HDFError(hdf5::Error),
+    BadFileFormat(PathBuf),
+}
+
+impl From<hdf5::Error> for PedestalError {
+    fn from(value: hdf5::Error) -> Self {
+        Self::HDFError(value)
+    }
+}
+
+impl Display for PedestalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::HDFError(e) => write!(f, "Pedestal table recieved an HDF5 error: {}", e),
+            Self::BadFileFormat(path) => write!(
+                f,
+                "Pedestal table found a bad file format while reading the pedestal file {}!",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl Error for PedestalError {}
+
 /*
    AsadStack errors
 */
@@ -251,9 +321,16 @@ impl Error for EvtStackError {}
 pub enum AsadStackError {
     IOError(std::io::Error),
     FileError(GrawFileError),
+    IndexError(FrameIndexError),
     NoMatchingFiles,
 }
 
+impl From<FrameIndexError> for AsadStackError {
+    fn from(value: FrameIndexError) -> Self {
+        Self::IndexError(value)
+    }
+}
+
 impl From<GrawFileError> for AsadStackError {
     fn from(value: GrawFileError) -> Self {
         Self::FileError(value)
@@ -271,6 +348,7 @@ impl Display for AsadStackError {
         match self {
             Self::IOError(e) => write!(f, "AsadStack recieved an io error: {}", e),
             Self::FileError(e) => write!(f, "AsadStack recieved a file error: {}", e),
+            Self::IndexError(e) => write!(f, "AsadStack recieved a frame index error: {}", e),
             Self::NoMatchingFiles => write!(f, "AsadStack couldn't find any matching files!"),
         }
     }
@@ -278,6 +356,33 @@ impl Display for AsadStackError {
 
 impl Error for AsadStackError {}
 
+/*
+   FrameSource errors
+*/
+
+#[derive(Debug)]
+pub enum FrameSourceError {
+    StackError(AsadStackError),
+    SourceError(String),
+}
+
+impl From<AsadStackError> for FrameSourceError {
+    fn from(value: AsadStackError) -> Self {
+        Self::StackError(value)
+    }
+}
+
+impl Display for FrameSourceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::StackError(e) => write!(f, "FrameSource recieved a stack error: {}", e),
+            Self::SourceError(e) => write!(f, "FrameSource recieved an error: {}", e),
+        }
+    }
+}
+
+impl Error for FrameSourceError {}
+
 /*
    PadMap errors
 */
@@ -313,6 +418,190 @@ impl Display for PadMapError {
 
 impl Error for PadMapError {}
 
+/*
+   PadGeometry errors
+*/
+
+#[derive(Debug)]
+pub enum PadGeometryError {
+    IOError(std::io::Error),
+    ParsingIntError(std::num::ParseIntError),
+    ParsingFloatError(std::num::ParseFloatError),
+    BadFileFormat,
+}
+
+impl From<std::io::Error> for PadGeometryError {
+    fn from(value: std::io::Error) -> Self {
+        PadGeometryError::IOError(value)
+    }
+}
+
+impl From<std::num::ParseIntError> for PadGeometryError {
+    fn from(value: std::num::ParseIntError) -> Self {
+        PadGeometryError::ParsingIntError(value)
+    }
+}
+
+impl From<std::num::ParseFloatError> for PadGeometryError {
+    fn from(value: std::num::ParseFloatError) -> Self {
+        PadGeometryError::ParsingFloatError(value)
+    }
+}
+
+impl Display for PadGeometryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PadGeometryError::IOError(e) => write!(f, "PadGeometry recieved an io error: {}", e),
+            PadGeometryError::ParsingIntError(e) => {
+                write!(f, "PadGeometry recieved a parsing error: {}", e)
+            }
+            PadGeometryError::ParsingFloatError(e) => {
+                write!(f, "PadGeometry recieved a parsing error: {}", e)
+            }
+            PadGeometryError::BadFileFormat => write!(
+                f,
+                "PadGeometry found a bad file format while reading the geometry file! Expected .csv without whitespaces"
+            ),
+        }
+    }
+}
+
+impl Error for PadGeometryError {}
+
+/*
+   Calibration errors
+*/
+
+#[derive(Debug)]
+pub enum CalibrationError {
+    IOError(std::io::Error),
+    ParsingIntError(std::num::ParseIntError),
+    ParsingFloatError(std::num::ParseFloatError),
+    BadFileFormat,
+}
+
+impl From<std::io::Error> for CalibrationError {
+    fn from(value: std::io::Error) -> Self {
+        CalibrationError::IOError(value)
+    }
+}
+
+impl From<std::num::ParseIntError> for CalibrationError {
+    fn from(value: std::num::ParseIntError) -> Self {
+        CalibrationError::ParsingIntError(value)
+    }
+}
+
+impl From<std::num::ParseFloatError> for CalibrationError {
+    fn from(value: std::num::ParseFloatError) -> Self {
+        CalibrationError::ParsingFloatError(value)
+    }
+}
+
+impl Display for CalibrationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CalibrationError::IOError(e) => {
+                write!(f, "GainCalibration recieved an io error: {}", e)
+            }
+            CalibrationError::ParsingIntError(e) => {
+                write!(f, "GainCalibration recieved a parsing error: {}", e)
+            }
+            CalibrationError::ParsingFloatError(e) => {
+                write!(f, "GainCalibration recieved a parsing error: {}", e)
+            }
+            CalibrationError::BadFileFormat => write!(
+                f,
+                "GainCalibration found a bad file format while reading the calibration file! Expected .csv rows of 3 or 6 elements without whitespaces"
+            ),
+        }
+    }
+}
+
+impl Error for CalibrationError {}
+
+/*
+   ChannelMask errors
+*/
+
+#[derive(Debug)]
+pub enum ChannelMaskError {
+    IOError(std::io::Error),
+    ParsingError(std::num::ParseIntError),
+    BadFileFormat,
+}
+
+impl From<std::io::Error> for ChannelMaskError {
+    fn from(value: std::io::Error) -> Self {
+        ChannelMaskError::IOError(value)
+    }
+}
+
+impl From<std::num::ParseIntError> for ChannelMaskError {
+    fn from(value: std::num::ParseIntError) -> Self {
+        ChannelMaskError::ParsingError(value)
+    }
+}
+
+impl Display for ChannelMaskError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChannelMaskError::IOError(e) => write!(f, "ChannelMask recieved an io error: {}", e),
+            ChannelMaskError::ParsingError(e) => {
+                write!(f, "ChannelMask recieved a parsing error: {}", e)
+            }
+            ChannelMaskError::BadFileFormat => write!(
+                f,
+                "ChannelMask found a bad file format while reading the mask file! Expected .csv rows of CoBo, AsAd, AGET, Channel, status"
+            ),
+        }
+    }
+}
+
+impl Error for ChannelMaskError {}
+
+/*
+   Deconvolution errors
+*/
+
+#[derive(Debug)]
+pub enum DeconvolutionError {
+    IOError(std::io::Error),
+    ParsingError(std::num::ParseFloatError),
+    EmptyResponse,
+}
+
+impl From<std::io::Error> for DeconvolutionError {
+    fn from(value: std::io::Error) -> Self {
+        DeconvolutionError::IOError(value)
+    }
+}
+
+impl From<std::num::ParseFloatError> for DeconvolutionError {
+    fn from(value: std::num::ParseFloatError) -> Self {
+        DeconvolutionError::ParsingError(value)
+    }
+}
+
+impl Display for DeconvolutionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeconvolutionError::IOError(e) => {
+                write!(f, "Deconvolution recieved an io error: {}", e)
+            }
+            DeconvolutionError::ParsingError(e) => {
+                write!(f, "Deconvolution recieved a parsing error: {}", e)
+            }
+            DeconvolutionError::EmptyResponse => write!(
+                f,
+                "Deconvolution response function is empty! The response must have a positive sum"
+            ),
+        }
+    }
+}
+
+impl Error for DeconvolutionError {}
+
 /*
    Event errors
 */
@@ -321,13 +610,15 @@ impl Error for PadMapError {}
 pub enum EventError {
     InvalidHardware(u8, u8, u8, u8),
     MismatchedEventID(u32, u32),
+    BadTimeBucket(u16),
 }
 
 impl Display for EventError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             EventError::InvalidHardware(cb, ad, ag, ch) => write!(f, "Event found hardware which does not correspond to a valid pad! CoBo: {}, AsAd: {}, AGET: {}, Channel: {}", cb, ad, ag, ch),
-            EventError::MismatchedEventID(given, exp) => write!(f, "Event was given a mismatched event id! Given: {}, Expected: {}", given, exp)
+            EventError::MismatchedEventID(given, exp) => write!(f, "Event was given a mismatched event id! Given: {}, Expected: {}", given, exp),
+            EventError::BadTimeBucket(bucket) => write!(f, "Event was given a sample with an invalid time bucket: {}", bucket)
         }
     }
 }
@@ -341,6 +632,7 @@ impl Error for EventError {}
 #[derive(Debug)]
 pub enum MergerError {
     AsadError(AsadStackError),
+    SourceError(FrameSourceError),
     NoFilesError,
     IOError(std::io::Error),
     ConfigError(ConfigError),
@@ -352,6 +644,12 @@ impl From<AsadStackError> for MergerError {
     }
 }
 
+impl From<FrameSourceError> for MergerError {
+    fn from(value: FrameSourceError) -> Self {
+        MergerError::SourceError(value)
+    }
+}
+
 impl From<std::io::Error> for MergerError {
     fn from(value: std::io::Error) -> Self {
         MergerError::IOError(value)
@@ -370,6 +668,13 @@ impl Display for MergerError {
             MergerError::AsadError(e) => {
                 write!(f, "A stack error occurred while merging! Error: {}", e)
             }
+            MergerError::SourceError(e) => {
+                write!(
+                    f,
+                    "A frame source error occurred while merging! Error: {}",
+                    e
+                )
+            }
             MergerError::NoFilesError => {
                 write!(f, "Merger could not find any files with .graw extension!")
             }
@@ -389,7 +694,7 @@ impl Error for MergerError {}
 
 #[derive(Debug)]
 pub enum EventBuilderError {
-    EventOutOfOrder(u32, u32),
+    DuplicateFrame(u8, u8, u32),
     EventError(EventError),
 }
 
@@ -402,14 +707,48 @@ impl From<EventError> for EventBuilderError {
 impl Display for EventBuilderError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::EventOutOfOrder(frame, event) => write!(f, "The event builder recieved a frame that is out of order -- frame event id: {} event builder event id: {}", frame, event),
-            Self::EventError(val) => write!(f, "The EventBuilder recieved an event error: {}", val)
+            Self::DuplicateFrame(cobo, asad, event) => write!(
+                f,
+                "The event builder recieved a duplicate frame -- CoBo: {} AsAd: {} event id: {}",
+                cobo, asad, event
+            ),
+            Self::EventError(val) => write!(f, "The EventBuilder recieved an event error: {}", val),
         }
     }
 }
 
 impl Error for EventBuilderError {}
 
+/*
+   EventRange errors
+*/
+#[derive(Debug)]
+pub enum EventRangeError {
+    BadEventId(std::num::ParseIntError),
+    Reversed(u32, u32),
+}
+
+impl From<std::num::ParseIntError> for EventRangeError {
+    fn from(value: std::num::ParseIntError) -> Self {
+        EventRangeError::BadEventId(value)
+    }
+}
+
+impl Display for EventRangeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BadEventId(e) => write!(f, "EventRange recieved an invalid event id: {}", e),
+            Self::Reversed(first, last) => write!(
+                f,
+                "EventRange {}-{} is reversed, the first event id must not be larger than the last!",
+                first, last
+            ),
+        }
+    }
+}
+
+impl Error for EventRangeError {}
+
 /*
    Config errors
 */
@@ -418,6 +757,8 @@ pub enum ConfigError {
     BadFilePath(PathBuf),
     IOError(std::io::Error),
     ParsingError(serde_yaml::Error),
+    MissingOption(String),
+    InvalidOption(String),
 }
 
 impl From<std::io::Error> for ConfigError {
@@ -440,6 +781,8 @@ impl Display for ConfigError {
             }
             Self::IOError(e) => write!(f, "Config received an io error: {}", e),
             Self::ParsingError(e) => write!(f, "Config received a parsing error: {}", e),
+            Self::MissingOption(option) => write!(f, "Config option {} must be set!", option),
+            Self::InvalidOption(option) => write!(f, "Config option {} is invalid!", option),
         }
     }
 }
@@ -455,6 +798,48 @@ pub enum ProcessorError {
     MapError(PadMapError),
     EvtError(EvtStackError),
     BadRingConversion(EvtItemError),
+    IOError(std::io::Error),
+    PedestalError(PedestalError),
+    GeometryError(PadGeometryError),
+    CalibrationError(CalibrationError),
+    MaskError(ChannelMaskError),
+    DeconvolutionError(DeconvolutionError),
+}
+
+impl From<DeconvolutionError> for ProcessorError {
+    fn from(value: DeconvolutionError) -> Self {
+        Self::DeconvolutionError(value)
+    }
+}
+
+impl From<ChannelMaskError> for ProcessorError {
+    fn from(value: ChannelMaskError) -> Self {
+        Self::MaskError(value)
+    }
+}
+
+impl From<CalibrationError> for ProcessorError {
+    fn from(value: CalibrationError) -> Self {
+        Self::CalibrationError(value)
+    }
+}
+
+impl From<PadGeometryError> for ProcessorError {
+    fn from(value: PadGeometryError) -> Self {
+        Self::GeometryError(value)
+    }
+}
+
+impl From<PedestalError> for ProcessorError {
+    fn from(value: PedestalError) -> Self {
+        Self::PedestalError(value)
+    }
+}
+
+impl From<std::io::Error> for ProcessorError {
+    fn from(value: std::io::Error) -> Self {
+        Self::IOError(value)
+    }
 }
 
 impl From<MergerError> for ProcessorError {
@@ -511,6 +896,16 @@ impl Display for ProcessorError {
             Self::BadRingConversion(e) => {
                 write!(f, "Processor failed due to bad ring item conversion: {}", e)
             }
+            Self::IOError(e) => write!(f, "Processor recieved an io error: {}", e),
+            Self::PedestalError(e) => write!(f, "Processor failed due to pedestal error: {}", e),
+            Self::GeometryError(e) => write!(f, "Processor failed due to PadGeometry error: {}", e),
+            Self::CalibrationError(e) => {
+                write!(f, "Processor failed due to calibration error: {}", e)
+            }
+            Self::MaskError(e) => write!(f, "Processor failed due to channel mask error: {}", e),
+            Self::DeconvolutionError(e) => {
+                write!(f, "Processor failed due to deconvolution error: {}", e)
+            }
         }
     }
 }
@@ -1,73 +1,380 @@
 use fxhash::FxHashMap;
-use ndarray::{s, Array1, Array2};
+use ndarray::{Array1, Array2, ArrayView1, ArrayViewMut1};
 
+use super::baseline::{estimate_baseline, Baseline};
+use super::calibration::{GainCalibration, PadGain};
+use super::completeness::board_bit;
+use super::config::{
+    BaselineConfig, DeconvolutionConfig, HitConfig, MergeMode, PointCloudConfig, SamplingConfig,
+    SaturationConfig, TraceOrder,
+};
 use super::constants::*;
+use super::deconvolution::{deconvolve, ResponseFunction};
 use super::error::EventError;
 use super::graw_frame::GrawFrame;
+use super::hits::{find_hits, find_hits_in_values, hits_to_matrix, Hit};
+use super::pad_geometry::PadGeometry;
 use super::pad_map::{HardwareID, PadMap};
+use super::pedestal::PedestalTable;
+use super::saturation::{saturation_to_matrix, Saturation};
+use super::sparse::SparseTraces;
 
 const FPN_CHANNELS: [u8; 4] = [11, 22, 45, 56]; //From AGET docs
 
-/// Simple baseline subtraction for a trace
-#[allow(dead_code)]
-fn subtract_trace_baseline(trace: &mut Array1<i16>) {
-    let sum: i16 = trace.iter().sum();
-    let mut n_nonzero: i16 = 0;
-    for element in trace.iter() {
-        if *element != 0 {
-            n_nonzero += 1;
+/// (cobo, asad, aget) -> (sum, count) of the FPN samples in each bucket
+type FpnSums = FxHashMap<(usize, usize, usize), (Array1<f64>, Array1<f64>)>;
+
+/// Sort the entries of a map keyed by HardwareID in the given order, so that output does not depend on the hash map order
+fn sorted_by_order<V>(map: &FxHashMap<HardwareID, V>, order: TraceOrder) -> Vec<(&HardwareID, &V)> {
+    let mut sorted: Vec<(&HardwareID, &V)> = map.iter().collect();
+    sorted.sort_unstable_by_key(|(hw_id, _)| hw_id.sort_key(order));
+    sorted
+}
+
+/// Iterate over the traces of a contiguous trace matrix with rows of the given width, with the HardwareID of each row
+fn rows<'a>(
+    ids: &'a [HardwareID],
+    data: &'a [i16],
+    width: usize,
+) -> impl Iterator<Item = (&'a HardwareID, ArrayView1<'a, i16>)> {
+    ids.iter().zip(
+        data.chunks_exact(width)
+            .map(|row| ArrayView1::from(&row[5..])),
+    )
+}
+
+/// Mutably iterate over the traces of a contiguous trace matrix with rows of the given width, with the HardwareID of each row
+fn rows_mut<'a>(
+    ids: &'a [HardwareID],
+    data: &'a mut [i16],
+    width: usize,
+) -> impl Iterator<Item = (&'a HardwareID, ArrayViewMut1<'a, i16>)> {
+    ids.iter().zip(
+        data.chunks_exact_mut(width)
+            .map(|row| ArrayViewMut1::from(&mut row[5..])),
+    )
+}
+
+/// Iterate over the read masks of the traces of a contiguous read mask with rows of the given width (see Event)
+fn read_rows(read: &[bool], width: usize) -> impl Iterator<Item = &[bool]> {
+    read.chunks_exact(width).map(|row| &row[5..])
+}
+
+/// The rows of a trace matrix in the given order
+fn sorted_rows(ids: &[HardwareID], order: TraceOrder) -> Vec<usize> {
+    let mut sorted: Vec<usize> = (0..ids.len()).collect();
+    sorted.sort_unstable_by_key(|row| ids[*row].sort_key(order));
+    sorted
+}
+
+/// Reorder the rows of a trace matrix in place, so that new row i is old row order[i]. Each cycle of the permutation is
+/// followed with a single row buffer, so no second matrix is allocated.
+fn permute_rows(data: &mut [i16], order: &[usize], width: usize) {
+    let mut placed = vec![false; order.len()];
+    let mut buffer = vec![0i16; width];
+    for start in 0..order.len() {
+        if placed[start] || order[start] == start {
+            continue;
+        }
+        buffer.copy_from_slice(&data[start * width..(start + 1) * width]);
+        let mut row = start;
+        loop {
+            placed[row] = true;
+            let source = order[row];
+            if source == start {
+                data[row * width..(row + 1) * width].copy_from_slice(&buffer);
+                break;
+            }
+            data.copy_within(source * width..(source + 1) * width, row * width);
+            row = source;
         }
     }
-    let baseline = sum / n_nonzero;
-    *trace -= baseline;
+}
+
+/// Crop and rebin the traces of a contiguous trace matrix and its read mask in place, see Event::resample. The address columns
+/// of each row are kept. Returns the new row width.
+fn resample_rows(
+    data: &mut Vec<i16>,
+    read: &mut Vec<bool>,
+    width: usize,
+    config: &SamplingConfig,
+) -> usize {
+    let n_buckets = config.get_n_buckets();
+    let new_width = 5 + n_buckets;
+    let n_rows = data.len() / width;
+    let mut buffer = vec![0i16; new_width];
+    let mut read_buffer = vec![false; new_width];
+    for row in 0..n_rows {
+        let old = &data[row * width..(row + 1) * width];
+        let old_read = &read[row * width..(row + 1) * width];
+        buffer[..5].copy_from_slice(&old[..5]);
+        let trace = &old[5 + config.first_bucket..];
+        let trace_read = &old_read[5 + config.first_bucket..];
+        for (bucket, (group, group_read)) in trace
+            .chunks_exact(config.rebin)
+            .zip(trace_read.chunks_exact(config.rebin))
+            .take(n_buckets)
+            .enumerate()
+        {
+            // Average the buckets which were read, leaving the bucket empty if none were
+            let (sum, count) = group
+                .iter()
+                .zip(group_read.iter())
+                .filter(|(_, read)| **read)
+                .fold((0.0, 0.0), |(sum, count), (sample, _)| {
+                    (sum + *sample as f64, count + 1.0)
+                });
+            buffer[5 + bucket] = if count > 0.0 {
+                (sum / count).round() as i16
+            } else {
+                0
+            };
+            read_buffer[5 + bucket] = count > 0.0;
+        }
+        data[row * new_width..(row + 1) * new_width].copy_from_slice(&buffer);
+        read[row * new_width..(row + 1) * new_width].copy_from_slice(&read_buffer);
+    }
+    data.truncate(n_rows * new_width);
+    read.truncate(n_rows * new_width);
+    new_width
+}
+
+/// The calibration of a trace, or the identity if the event is not calibrated
+fn gain_of(gains: &Option<FxHashMap<HardwareID, PadGain>>, hw_id: &HardwareID) -> PadGain {
+    gains
+        .as_ref()
+        .and_then(|gains| gains.get(hw_id).copied())
+        .unwrap_or_default()
+}
+
+/// Calibrate a sample. Empty (unread) samples are left empty.
+fn calibrate_sample(gain: &PadGain, sample: i16, read: bool) -> f32 {
+    if read {
+        gain.apply(sample as f64) as f32
+    } else {
+        0.0
+    }
 }
 
 /// # Event
 /// An event is a collection of traces which all occured with the same Event ID generated by the AT-TPC DAQ.
 /// An event is created from a Vec of GrawFrames, which are then parsed into ndarray traces. The event can also subtract
-/// the fixed pattern noise recored by the electronics. To write the event to HDF5, convert the event to a data matrix.
+/// the fixed pattern noise recored by the electronics (see TraceProcessor). To write the event to HDF5, convert the event to a
+/// data matrix.
+///
+/// The traces are stored in one contiguous matrix, in the same format as the data matrix (the hardware address and pad of the
+/// trace followed by its samples), with an index from HardwareID to row. Rows are added as new pads are found while appending
+/// frames, so an event allocates a single buffer rather than an array per pad, and converting to a data matrix reuses it.
+///
+/// A read mask with the same layout records which buckets were read. Written traces use 0 for buckets which were not read, but
+/// a read sample can also become 0 once pedestals, noise, or baselines are subtracted, so the stages which skip empty buckets
+/// (and the sparse format) use the mask rather than the sample value.
+///
+/// With MergeMode::Timestamp the frames of an event are grouped by timestamp and may have different Event IDs. In that case the
+/// event takes the Event ID of its first frame.
+///
+/// Everything written per trace (traces, FPN traces, baselines, hits, saturation) is sorted by the TraceOrder of the event, so
+/// that converting the same data twice gives identical output.
 #[derive(Debug)]
 pub struct Event {
     nframes: i32,
-    traces: FxHashMap<HardwareID, Array1<i16>>, //maps pad id to the trace for that pad
+    data: Vec<i16>,                     //contiguous trace matrix, row_width per row
+    row_width: usize,                   //NUMBER_OF_MATRIX_COLUMNS, unless the traces were resampled
+    rows: FxHashMap<HardwareID, usize>, //maps a pad to its row in data
+    ids: Vec<HardwareID>,               //the pad of each row in data
+    read: Vec<bool>,                    //which entries of data were read, same layout as data
+    fpn_data: Option<Array2<i16>>, //FPN traces removed by subtract_fixed_pattern_noise, if kept
+    fpn_read: Vec<bool>,           //which entries of fpn_data were read
+    baselines: FxHashMap<HardwareID, Baseline>, //baselines removed by subtract_baselines
+    hits: Vec<Hit>,                //hits found by find_hits
+    deconvolved: FxHashMap<HardwareID, Array1<f64>>, //made by deconvolve, if written
+    deconvolved_hits: Option<Vec<Hit>>, //hits found by deconvolve
+    point_cloud: Option<Array2<f64>>, //made by build_point_cloud
+    gains: Option<FxHashMap<HardwareID, PadGain>>, //set by apply_calibration
+    saturation: Option<FxHashMap<HardwareID, Saturation>>, //saturated traces found by find_saturation
     pub timestamp: u64,
     pub timestampother: u64,
     pub event_id: u32,
+    pub epoch: u32, // number of event counter resets before this event, see Merger
+    pub contributors: u64, // bitmask of the CoBo/AsAds which contributed a frame, see completeness::board_bit
+    pub merge_mode: MergeMode, // the rule used to group the frames of this event
+    pub trace_order: TraceOrder, // the order in which traces are written
 }
 
 impl Event {
     /// Make a new event from a list of GrawFrames
-    pub fn new(pad_map: &PadMap, frames: &Vec<GrawFrame>) -> Result<Self, EventError> {
+    pub fn new(
+        pad_map: &PadMap,
+        frames: &Vec<GrawFrame>,
+        merge_mode: MergeMode,
+        trace_order: TraceOrder,
+    ) -> Result<Self, EventError> {
         let mut event = Event {
             nframes: 0,
-            traces: FxHashMap::default(),
+            data: Vec::new(),
+            row_width: NUMBER_OF_MATRIX_COLUMNS,
+            rows: FxHashMap::default(),
+            ids: Vec::new(),
+            read: Vec::new(),
+            fpn_data: None,
+            fpn_read: Vec::new(),
+            baselines: FxHashMap::default(),
+            hits: Vec::new(),
+            deconvolved: FxHashMap::default(),
+            deconvolved_hits: None,
+            point_cloud: None,
+            gains: None,
+            saturation: None,
             timestamp: 0,
             timestampother: 0,
             event_id: 0,
+            epoch: 0,
+            contributors: 0,
+            merge_mode,
+            trace_order,
         };
         for frame in frames {
             event.append_frame(pad_map, frame)?;
         }
-
-        // This isn't used
-        //event.subtract_fixed_pattern_noise(pad_map);
         Ok(event)
     }
 
     /// Convert the event traces to a data matrix for writing to disk. Follows format used by AT-TPC analysis
     pub fn convert_to_data_matrix(self) -> Array2<i16> {
-        let mut data_matrix = Array2::<i16>::zeros([self.traces.len(), NUMBER_OF_MATRIX_COLUMNS]);
-        for (row, (hw_id, trace)) in self.traces.into_iter().enumerate() {
-            data_matrix[[row, 0]] = hw_id.cobo_id as i16;
-            data_matrix[[row, 1]] = hw_id.asad_id as i16;
-            data_matrix[[row, 2]] = hw_id.aget_id as i16;
-            data_matrix[[row, 3]] = hw_id.channel as i16;
-            data_matrix[[row, 4]] = hw_id.pad_id as i16;
-            let mut trace_slice = data_matrix.slice_mut(s![row, 5..NUMBER_OF_MATRIX_COLUMNS]);
-            trace.move_into(&mut trace_slice);
+        let order = sorted_rows(&self.ids, self.trace_order);
+        let mut data = self.data;
+        permute_rows(&mut data, &order, self.row_width);
+        Array2::from_shape_vec((order.len(), self.row_width), data)
+            .expect("Event trace matrix always has full rows")
+    }
+
+    /// Convert the calibrated event traces to a data matrix for writing to disk. Same format as convert_to_data_matrix, but as
+    /// f32. Traces are calibrated with the gains set by apply_calibration.
+    pub fn convert_to_calibrated_data_matrix(self) -> Array2<f32> {
+        let mut data_matrix = Array2::<f32>::zeros([self.ids.len(), self.row_width]);
+        for (row, (hw_id, trace, read)) in self.iter_sorted_read_traces().enumerate() {
+            let gain = gain_of(&self.gains, hw_id);
+            data_matrix[[row, 0]] = hw_id.cobo_id as f32;
+            data_matrix[[row, 1]] = hw_id.asad_id as f32;
+            data_matrix[[row, 2]] = hw_id.aget_id as f32;
+            data_matrix[[row, 3]] = hw_id.channel as f32;
+            data_matrix[[row, 4]] = hw_id.pad_id as f32;
+            for (bucket, (sample, read)) in trace.iter().zip(read.iter()).enumerate() {
+                data_matrix[[row, 5 + bucket]] = calibrate_sample(&gain, *sample, *read);
+            }
+        }
+        data_matrix
+    }
+
+    /// Convert the event traces to the sparse format for writing to disk. See SparseTraces for the layout.
+    pub fn convert_to_sparse(self, threshold: Option<i16>) -> SparseTraces<i16> {
+        SparseTraces::new(self.iter_sorted_read_traces(), threshold, |_, sample| {
+            sample
+        })
+    }
+
+    /// Convert the calibrated event traces to the sparse format for writing to disk. The threshold applies to the
+    /// uncalibrated samples.
+    pub fn convert_to_calibrated_sparse(self, threshold: Option<i16>) -> SparseTraces<f32> {
+        let gains = &self.gains;
+        SparseTraces::new(
+            self.iter_sorted_read_traces(),
+            threshold,
+            |hw_id, sample| calibrate_sample(&gain_of(gains, hw_id), sample, true),
+        )
+    }
+
+    /// Check if the event has been calibrated (see apply_calibration)
+    pub fn is_calibrated(&self) -> bool {
+        self.gains.is_some()
+    }
+
+    /// Take the FPN traces kept by subtract_fixed_pattern_noise as a data matrix, in the same format as the event traces.
+    /// Returns None if no FPN traces were kept.
+    pub fn take_fpn_data_matrix(&mut self) -> Option<Array2<i16>> {
+        self.fpn_data.take()
+    }
+
+    /// Take the baselines found by subtract_baselines as a matrix. Each row is the hardware address and pad of a trace
+    /// (CoBo, AsAd, AGET, Channel, Pad) followed by the baseline and its RMS. Returns None if no baselines were found.
+    pub fn take_baseline_matrix(&mut self) -> Option<Array2<f64>> {
+        if self.baselines.is_empty() {
+            return None;
+        }
+        let mut matrix = Array2::<f64>::zeros([self.baselines.len(), 7]);
+        let baselines = std::mem::take(&mut self.baselines);
+        for (row, (hw_id, baseline)) in sorted_by_order(&baselines, self.trace_order)
+            .into_iter()
+            .enumerate()
+        {
+            matrix[[row, 0]] = hw_id.cobo_id as f64;
+            matrix[[row, 1]] = hw_id.asad_id as f64;
+            matrix[[row, 2]] = hw_id.aget_id as f64;
+            matrix[[row, 3]] = hw_id.channel as f64;
+            matrix[[row, 4]] = hw_id.pad_id as f64;
+            matrix[[row, 5]] = baseline.value;
+            matrix[[row, 6]] = baseline.rms;
+        }
+        Some(matrix)
+    }
+
+    /// The hits found by find_hits
+    pub fn get_hits(&self) -> &[Hit] {
+        &self.hits
+    }
+
+    /// The hit table of the event, one row per hit (see Hit::get_row)
+    pub fn get_hit_matrix(&self) -> Array2<f64> {
+        hits_to_matrix(&self.hits)
+    }
+
+    /// The saturated traces found by find_saturation
+    pub fn iter_saturation(&self) -> impl Iterator<Item = &Saturation> {
+        self.saturation
+            .iter()
+            .flat_map(|saturated| saturated.values())
+    }
+
+    /// The saturation table of the event (see saturation::saturation_to_matrix). Returns None if find_saturation was not
+    /// run.
+    pub fn get_saturation_matrix(&self) -> Option<Array2<u32>> {
+        self.saturation.as_ref().map(|saturated| {
+            saturation_to_matrix(sorted_by_order(saturated, self.trace_order).into_iter())
+        })
+    }
+
+    /// Take the deconvolved traces made by deconvolve as a data matrix, in the same format as the calibrated event traces (f32).
+    /// Returns None if no deconvolved traces were kept.
+    pub fn take_deconvolved_matrix(&mut self) -> Option<Array2<f32>> {
+        if self.deconvolved.is_empty() {
+            return None;
         }
+        let deconvolved = std::mem::take(&mut self.deconvolved);
+        let mut matrix = Array2::<f32>::zeros([deconvolved.len(), NUMBER_OF_MATRIX_COLUMNS]);
+        for (row, (hw_id, trace)) in sorted_by_order(&deconvolved, self.trace_order)
+            .into_iter()
+            .enumerate()
+        {
+            matrix[[row, 0]] = hw_id.cobo_id as f32;
+            matrix[[row, 1]] = hw_id.asad_id as f32;
+            matrix[[row, 2]] = hw_id.aget_id as f32;
+            matrix[[row, 3]] = hw_id.channel as f32;
+            matrix[[row, 4]] = hw_id.pad_id as f32;
+            for (bucket, value) in trace.iter().enumerate() {
+                matrix[[row, 5 + bucket]] = *value as f32;
+            }
+        }
+        Some(matrix)
+    }
 
-        return data_matrix;
+    /// The hit table of the deconvolved traces (see Hit::get_row). Returns None if deconvolve did not find hits.
+    pub fn get_deconvolved_hit_matrix(&self) -> Option<Array2<f64>> {
+        self.deconvolved_hits.as_deref().map(hits_to_matrix)
+    }
+
+    /// Take the point cloud made by build_point_cloud. Returns None if no point cloud was made.
+    pub fn take_point_cloud(&mut self) -> Option<Array2<f64>> {
+        self.point_cloud.take()
     }
 
     pub fn get_header_array(&self) -> Array1<f64> {
@@ -75,15 +382,97 @@ impl Event {
             self.event_id as f64,
             self.timestamp as f64,
             self.timestampother as f64,
+            self.merge_mode as u8 as f64,
+            self.epoch as f64,
+            self.contributors as f64, // at most 44 bits, exact in f64
         ])
     }
 
+    /// The number of traces (pads) in the event
+    pub fn get_number_of_traces(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Iterate over the traces of the event and their hardware addresses
+    pub fn iter_traces(&self) -> impl Iterator<Item = (&HardwareID, ArrayView1<i16>)> {
+        rows(&self.ids, &self.data, self.row_width)
+    }
+
+    /// Iterate over the traces of the event in the TraceOrder of the event
+    fn iter_sorted_traces(&self) -> impl Iterator<Item = (&HardwareID, ArrayView1<i16>)> {
+        sorted_rows(&self.ids, self.trace_order)
+            .into_iter()
+            .map(|row| (&self.ids[row], self.get_trace(row)))
+    }
+
+    /// Iterate over the traces of the event and their read masks in the TraceOrder of the event
+    fn iter_sorted_read_traces(
+        &self,
+    ) -> impl Iterator<Item = (&HardwareID, ArrayView1<i16>, &[bool])> {
+        sorted_rows(&self.ids, self.trace_order)
+            .into_iter()
+            .map(|row| (&self.ids[row], self.get_trace(row), self.get_read(row)))
+    }
+
+    /// The trace in a row of the trace matrix
+    fn get_trace(&self, row: usize) -> ArrayView1<i16> {
+        let start = row * self.row_width;
+        ArrayView1::from(&self.data[start + 5..start + self.row_width])
+    }
+
+    /// The read mask of the trace in a row of the trace matrix
+    fn get_read(&self, row: usize) -> &[bool] {
+        let start = row * self.row_width;
+        &self.read[start + 5..start + self.row_width]
+    }
+
+    /// The sum of every sample of every trace in the event
+    pub fn get_total_charge(&self) -> i64 {
+        self.iter_traces()
+            .map(|(_, trace)| trace.iter().map(|sample| *sample as i64).sum::<i64>())
+            .sum()
+    }
+
+    /// Remove the rows flagged in remove from the trace matrix, keeping the order of the other rows
+    fn remove_rows(&mut self, remove: &[bool]) {
+        let mut kept = 0;
+        for row in 0..self.ids.len() {
+            if remove[row] {
+                continue;
+            }
+            if kept != row {
+                self.data.copy_within(
+                    row * self.row_width..(row + 1) * self.row_width,
+                    kept * self.row_width,
+                );
+                self.read.copy_within(
+                    row * self.row_width..(row + 1) * self.row_width,
+                    kept * self.row_width,
+                );
+                self.ids.swap(kept, row);
+            }
+            kept += 1;
+        }
+        self.data.truncate(kept * self.row_width);
+        self.read.truncate(kept * self.row_width);
+        self.ids.truncate(kept);
+        self.rows = self
+            .ids
+            .iter()
+            .enumerate()
+            .map(|(row, hw_id)| (hw_id.clone(), row))
+            .collect();
+    }
+
     /// Add a frame to the event. Sanity checks can return errors
     fn append_frame(&mut self, pad_map: &PadMap, frame: &GrawFrame) -> Result<(), EventError> {
         if self.nframes == 0 {
             //first frame
             self.event_id = frame.header.event_id;
-        } else if self.event_id != frame.header.event_id {
+            self.epoch = frame.epoch;
+        } else if self.merge_mode == MergeMode::EventId
+            && (self.event_id != frame.header.event_id || self.epoch != frame.epoch)
+        {
             return Err(EventError::MismatchedEventID(
                 frame.header.event_id,
                 self.event_id,
@@ -98,8 +487,15 @@ impl Event {
             self.timestamp = frame.header.event_time;
         }
 
+        self.contributors |= board_bit(frame.header.cobo_id, frame.header.asad_id);
+
         let mut hw_id: &HardwareID;
         for datum in frame.data.iter() {
+            // Writing past the trace would overwrite the address of the next row
+            if datum.time_bucket_id as u32 >= NUMBER_OF_TIME_BUCKETS {
+                return Err(EventError::BadTimeBucket(datum.time_bucket_id));
+            }
+
             hw_id = match pad_map.get_hardware_id(
                 &frame.header.cobo_id,
                 &frame.header.asad_id,
@@ -113,18 +509,29 @@ impl Event {
                 }
             };
 
-            match self.traces.get_mut(&hw_id) {
-                Some(trace) => {
-                    trace[datum.time_bucket_id as usize] = datum.sample;
-                }
+            let row = match self.rows.get(hw_id) {
+                Some(row) => *row,
                 None => {
-                    //First time this pad found during event. Create a new array
-                    let mut trace: Array1<i16> =
-                        Array1::<i16>::zeros(NUMBER_OF_TIME_BUCKETS as usize);
-                    trace[datum.time_bucket_id as usize] = datum.sample;
-                    self.traces.insert(hw_id.clone(), trace);
+                    //First time this pad found during event. Add a new row to the matrix
+                    let row = self.ids.len();
+                    self.data.extend_from_slice(&[
+                        hw_id.cobo_id as i16,
+                        hw_id.asad_id as i16,
+                        hw_id.aget_id as i16,
+                        hw_id.channel as i16,
+                        hw_id.pad_id as i16,
+                    ]);
+                    self.data
+                        .resize(self.data.len() + NUMBER_OF_TIME_BUCKETS as usize, 0);
+                    self.read.resize(self.data.len(), false);
+                    self.rows.insert(hw_id.clone(), row);
+                    self.ids.push(hw_id.clone());
+                    row
                 }
-            }
+            };
+            let idx = row * self.row_width + 5 + datum.time_bucket_id as usize;
+            self.data[idx] = datum.sample;
+            self.read[idx] = true;
         }
 
         self.nframes += 1;
@@ -132,168 +539,580 @@ impl Event {
         Ok(())
     }
 
-    /// Only for use in special cases! This will not throw errors when invalid pads are selected
-    #[allow(dead_code)]
-    fn get_trace_from_hardware_id(
-        &self,
-        pad_map: &PadMap,
-        cobo_id: &u8,
-        asad_id: &u8,
-        aget_id: &u8,
-        channel_id: &u8,
-    ) -> Option<&Array1<i16>> {
-        if let Some(hw_id) = pad_map.get_hardware_id(cobo_id, asad_id, aget_id, channel_id) {
-            return self.traces.get(hw_id);
-        } else {
-            return None;
+    /// Subtract the fixed pattern noise (FPN) from the traces. Each AGET has four channels (11, 22, 45, 56) which record the
+    /// noise pattern common to the chip. For each AGET with FPN traces in the event, the FPN traces are averaged bucket by bucket
+    /// (ignoring empty buckets) and the mean of the averaged pattern is removed, so that only the pattern itself is subtracted
+    /// from the other traces of that AGET. Empty buckets of the other traces are left empty. The FPN traces are then removed from
+    /// the event, and kept aside if keep_fpn is true (see take_fpn_data_matrix).
+    ///
+    /// The FPN channels must be in the pad map to be read from the data.
+    pub fn subtract_fixed_pattern_noise(&mut self, keep_fpn: bool) {
+        let is_fpn: Vec<bool> = self
+            .ids
+            .iter()
+            .map(|hw_id| FPN_CHANNELS.contains(&(hw_id.channel as u8)))
+            .collect();
+
+        let mut fpn_sums: FpnSums = FxHashMap::default();
+        for ((hw_id, trace), read) in self
+            .iter_traces()
+            .zip(read_rows(&self.read, self.row_width))
+            .zip(is_fpn.iter())
+            .filter_map(|(row, fpn)| fpn.then_some(row))
+        {
+            let (sum, count) = fpn_sums
+                .entry((hw_id.cobo_id, hw_id.asad_id, hw_id.aget_id))
+                .or_insert_with(|| {
+                    (
+                        Array1::<f64>::zeros(NUMBER_OF_TIME_BUCKETS as usize),
+                        Array1::<f64>::zeros(NUMBER_OF_TIME_BUCKETS as usize),
+                    )
+                });
+            for (idx, (sample, read)) in trace.iter().zip(read.iter()).enumerate() {
+                if *read {
+                    sum[idx] += *sample as f64;
+                    count[idx] += 1.0;
+                }
+            }
+        }
+
+        let mut mean_fpn: FxHashMap<(usize, usize, usize), Array1<f64>> = FxHashMap::default();
+        for (aget, (sum, count)) in fpn_sums.into_iter() {
+            let mut pattern = Array1::<f64>::zeros(NUMBER_OF_TIME_BUCKETS as usize);
+            let mut baseline_sum = 0.0;
+            let mut n_buckets = 0.0;
+            for idx in 0..(NUMBER_OF_TIME_BUCKETS as usize) {
+                if count[idx] > 0.0 {
+                    pattern[idx] = sum[idx] / count[idx];
+                    baseline_sum += pattern[idx];
+                    n_buckets += 1.0;
+                }
+            }
+            if n_buckets == 0.0 {
+                continue;
+            }
+            let baseline = baseline_sum / n_buckets;
+            for idx in 0..(NUMBER_OF_TIME_BUCKETS as usize) {
+                if count[idx] > 0.0 {
+                    pattern[idx] -= baseline;
+                }
+            }
+            mean_fpn.insert(aget, pattern);
+        }
+
+        if keep_fpn && is_fpn.contains(&true) {
+            let fpn_rows: Vec<usize> = sorted_rows(&self.ids, self.trace_order)
+                .into_iter()
+                .filter(|row| is_fpn[*row])
+                .collect();
+            let mut fpn_data: Vec<i16> = Vec::with_capacity(fpn_rows.len() * self.row_width);
+            self.fpn_read = Vec::with_capacity(fpn_rows.len() * self.row_width);
+            for row in fpn_rows.iter() {
+                let range = row * self.row_width..(row + 1) * self.row_width;
+                fpn_data.extend_from_slice(&self.data[range.clone()]);
+                self.fpn_read.extend_from_slice(&self.read[range]);
+            }
+            self.fpn_data = Some(
+                Array2::from_shape_vec((fpn_rows.len(), self.row_width), fpn_data)
+                    .expect("FPN trace matrix always has full rows"),
+            );
+        }
+        self.remove_rows(&is_fpn);
+
+        for ((hw_id, mut trace), read) in rows_mut(&self.ids, &mut self.data, self.row_width)
+            .zip(read_rows(&self.read, self.row_width))
+        {
+            if let Some(pattern) = mean_fpn.get(&(hw_id.cobo_id, hw_id.asad_id, hw_id.aget_id)) {
+                for ((sample, noise), read) in trace.iter_mut().zip(pattern.iter()).zip(read.iter())
+                {
+                    if *read {
+                        *sample = (*sample as f64 - noise).round() as i16;
+                    }
+                }
+            }
         }
     }
 
-    /// Only for use in special cases! This will not throw errors when invalid pads are selected
-    #[allow(dead_code)]
-    fn get_mutable_trace_from_hardware_id(
+    /// Estimate the baseline of each trace and subtract it from the samples of the trace. Empty buckets are left empty.
+    /// Traces with no samples are left as they are. The baseline and its RMS are kept for each trace (see take_baseline_matrix).
+    pub fn subtract_baselines(&mut self, config: &BaselineConfig) {
+        for ((hw_id, mut trace), read) in rows_mut(&self.ids, &mut self.data, self.row_width)
+            .zip(read_rows(&self.read, self.row_width))
+        {
+            if let Some(baseline) = estimate_baseline(&trace.view(), read, config) {
+                for (sample, read) in trace.iter_mut().zip(read.iter()) {
+                    if *read {
+                        *sample = (*sample as f64 - baseline.value).round() as i16;
+                    }
+                }
+                self.baselines.insert(hw_id.clone(), baseline);
+            }
+        }
+    }
+
+    /// Subtract the pedestal of each time bucket from the traces, using a table from a pedestal run. Empty buckets are left
+    /// empty. Traces of channels which are not in the table are left as they are.
+    pub fn subtract_pedestals(&mut self, table: &PedestalTable) {
+        for ((hw_id, mut trace), read) in rows_mut(&self.ids, &mut self.data, self.row_width)
+            .zip(read_rows(&self.read, self.row_width))
+        {
+            let address = (
+                hw_id.cobo_id as u8,
+                hw_id.asad_id as u8,
+                hw_id.aget_id as u8,
+                hw_id.channel as u8,
+            );
+            if let Some(entry) = table.get_entry(&address) {
+                for ((sample, pedestal), read) in
+                    trace.iter_mut().zip(entry.mean.iter()).zip(read.iter())
+                {
+                    if *read {
+                        *sample = (*sample as f32 - pedestal).round() as i16;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Find the hits (peaks) in every trace of the event. Should be done after any baseline subtraction. If the event is
+    /// calibrated, the hits are found in the calibrated traces.
+    pub fn find_hits(&mut self, config: &HitConfig) {
+        let mut hits: Vec<Hit> = Vec::new();
+        for (hw_id, trace) in self.iter_sorted_traces() {
+            let gain = gain_of(&self.gains, hw_id);
+            hits.append(&mut find_hits(hw_id, &trace, &gain, config));
+        }
+        self.hits = hits;
+    }
+
+    /// Convert the hits of the event to a point cloud. Each point is a row of x, y, z, amplitude, integral, pad, time bucket.
+    /// x and y are the position of the pad and z is found from the time bucket of the hit. Hits on pads which are not in the
+    /// geometry are skipped.
+    pub fn build_point_cloud(
         &mut self,
-        pad_map: &PadMap,
-        cobo_id: &u8,
-        asad_id: &u8,
-        aget_id: &u8,
-        channel_id: &u8,
-    ) -> Option<&mut Array1<i16>> {
-        if let Some(hw_id) = pad_map.get_hardware_id(cobo_id, asad_id, aget_id, channel_id) {
-            return self.traces.get_mut(hw_id);
-        } else {
-            return None;
+        geometry: &PadGeometry,
+        config: &PointCloudConfig,
+        sampling_frequency: f64,
+    ) {
+        let mut points: Vec<f64> = Vec::with_capacity(self.hits.len() * 7);
+        for hit in self.hits.iter() {
+            if let Some(position) = geometry.get_position(&hit.hw_id.pad_id) {
+                points.extend_from_slice(&[
+                    position.x,
+                    position.y,
+                    config.get_z(hit.time_bucket, sampling_frequency),
+                    hit.amplitude,
+                    hit.integral,
+                    hit.hw_id.pad_id as f64,
+                    hit.time_bucket,
+                ]);
+            }
         }
+        let n_points = points.len() / 7;
+        self.point_cloud = Some(
+            Array2::from_shape_vec((n_points, 7), points)
+                .expect("Point cloud rows always fit the matrix"),
+        );
+    }
+
+    /// Calibrate the event with per-pad gains. The traces keep their raw samples; the gains are applied when hits are found and
+    /// when the traces are converted for writing, which is then done as f32.
+    pub fn apply_calibration(&mut self, calibration: &GainCalibration) {
+        self.gains = Some(
+            self.ids
+                .iter()
+                .map(|hw_id| (hw_id.clone(), calibration.get_gain(hw_id)))
+                .collect(),
+        );
     }
 
-    /// Remove a trace, if it exists
-    #[allow(dead_code)]
-    fn remove_trace(
+    /// Deconvolve each trace with the AGET response (see deconvolution::deconvolve). The traces themselves are left as they are;
+    /// the deconvolved traces are kept if write_traces is set, and their hits are found if find_hits is set. If the event is
+    /// calibrated, the calibrated traces are deconvolved.
+    pub fn deconvolve(
         &mut self,
-        pad_map: &PadMap,
-        cobo_id: &u8,
-        asad_id: &u8,
-        aget_id: &u8,
-        channel_id: &u8,
+        response: &ResponseFunction,
+        config: &DeconvolutionConfig,
+        hit_config: &HitConfig,
     ) {
-        if let Some(hw_id) = pad_map.get_hardware_id(cobo_id, asad_id, aget_id, channel_id) {
-            self.traces.remove(hw_id);
+        let mut hits: Vec<Hit> = Vec::new();
+        let mut traces: FxHashMap<HardwareID, Array1<f64>> = FxHashMap::default();
+        for (hw_id, trace) in self.iter_sorted_traces() {
+            let gain = gain_of(&self.gains, hw_id);
+            let deconvolved = deconvolve(&trace, &gain, response, config.iterations);
+            if config.find_hits {
+                hits.append(&mut find_hits_in_values(
+                    hw_id,
+                    deconvolved.clone(),
+                    hit_config,
+                ));
+            }
+            if config.write_traces {
+                traces.insert(hw_id.clone(), deconvolved);
+            }
+        }
+        self.deconvolved = traces;
+        if config.find_hits {
+            self.deconvolved_hits = Some(hits);
         }
     }
 
-    /// Idk if this is even really used
-    #[allow(dead_code)]
-    fn subtract_fixed_pattern_noise(&mut self, pad_map: &PadMap) {
-        for cb_id in 0..NUMBER_OF_COBOS {
-            for ad_id in 0..NUMBER_OF_ASADS {
-                for ag_id in 0..NUMBER_OF_AGETS {
-                    let mut mean_fpn: Array1<i16>;
-                    {
-                        let noise1_trace: &Array1<i16>;
-                        let noise2_trace: &Array1<i16>;
-                        let noise3_trace: &Array1<i16>;
-                        let noise4_trace: &Array1<i16>;
-                        if let Some(n1) = self.get_trace_from_hardware_id(
-                            pad_map,
-                            &cb_id,
-                            &ad_id,
-                            &ag_id,
-                            &FPN_CHANNELS[0],
-                        ) {
-                            noise1_trace = n1;
-                        } else {
-                            self.remove_fpn_channels(pad_map, &cb_id, &ad_id, &ag_id);
-                            continue;
-                        }
-                        if let Some(n2) = self.get_trace_from_hardware_id(
-                            pad_map,
-                            &cb_id,
-                            &ad_id,
-                            &ag_id,
-                            &FPN_CHANNELS[1],
-                        ) {
-                            noise2_trace = n2;
-                        } else {
-                            self.remove_fpn_channels(pad_map, &cb_id, &ad_id, &ag_id);
-                            continue;
-                        }
-                        if let Some(n3) = self.get_trace_from_hardware_id(
-                            pad_map,
-                            &cb_id,
-                            &ad_id,
-                            &ag_id,
-                            &FPN_CHANNELS[2],
-                        ) {
-                            noise3_trace = n3;
-                        } else {
-                            self.remove_fpn_channels(pad_map, &cb_id, &ad_id, &ag_id);
-                            continue;
-                        }
-                        if let Some(n4) = self.get_trace_from_hardware_id(
-                            pad_map,
-                            &cb_id,
-                            &ad_id,
-                            &ag_id,
-                            &FPN_CHANNELS[3],
-                        ) {
-                            noise4_trace = n4;
-                        } else {
-                            self.remove_fpn_channels(pad_map, &cb_id, &ad_id, &ag_id);
-                            continue;
-                        }
-
-                        mean_fpn = noise1_trace + noise2_trace + noise3_trace + noise4_trace;
-                        for idx in 0..(NUMBER_OF_TIME_BUCKETS as usize) {
-                            let mut mult: i16 = 0;
-                            if noise1_trace[idx] != 0 {
-                                mult += 1;
-                            }
-                            if noise2_trace[idx] != 0 {
-                                mult += 1;
-                            }
-                            if noise3_trace[idx] != 0 {
-                                mult += 1;
-                            }
-                            if noise4_trace[idx] != 0 {
-                                mult += 1;
-                            }
-
-                            mean_fpn[idx] /= mult;
-                        }
-                    }
+    /// Crop the traces (and any kept FPN traces) to a range of time buckets, then average each group of rebin buckets into one
+    /// bucket. Empty buckets are not averaged, and a group with no buckets read is left empty. Must be done after every other
+    /// trace analysis, as the traces no longer have NUMBER_OF_TIME_BUCKETS buckets.
+    pub fn resample(&mut self, config: &SamplingConfig) {
+        if config.is_identity() {
+            return;
+        }
+        if let Some(fpn_data) = self.fpn_data.take() {
+            let n_rows = fpn_data.nrows();
+            let mut fpn_data = fpn_data.into_raw_vec();
+            let width = resample_rows(&mut fpn_data, &mut self.fpn_read, self.row_width, config);
+            self.fpn_data = Some(
+                Array2::from_shape_vec((n_rows, width), fpn_data)
+                    .expect("FPN trace matrix always has full rows"),
+            );
+        }
+        self.row_width = resample_rows(&mut self.data, &mut self.read, self.row_width, config);
+    }
 
-                    subtract_trace_baseline(&mut mean_fpn); //Correct for baseline of noise traces?
-
-                    //Remove the fixed-pattern noise from the remaining traces (note here we don't skip over fpn channels)
-                    for channel in 0..(NUMBER_OF_CHANNELS as usize) {
-                        if let Some(trace) = self.get_mutable_trace_from_hardware_id(
-                            pad_map,
-                            &cb_id,
-                            &ad_id,
-                            &ag_id,
-                            &(channel as u8),
-                        ) {
-                            *trace = trace
-                                .iter()
-                                .zip(mean_fpn.iter())
-                                .map(|(x, y)| x - y)
-                                .collect();
-                        } else {
-                            continue;
-                        }
-                    }
+    /// Count the buckets of each trace at the ceiling or floor of the ADC. Must be run on the raw samples, before any other
+    /// trace analysis. Only saturated traces are kept.
+    pub fn find_saturation(&mut self, config: &SaturationConfig) {
+        self.saturation = Some(
+            self.iter_traces()
+                .map(|(hw_id, trace)| (hw_id.clone(), Saturation::new(&trace, config)))
+                .filter(|(_, saturation)| saturation.is_saturated())
+                .collect(),
+        );
+    }
+}
 
-                    //Drop the fpn traces as we no longer need them
-                    self.remove_fpn_channels(pad_map, &cb_id, &ad_id, &ag_id);
-                }
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merger::graw_frame::GrawData;
+
+    fn make_sampling(first_bucket: usize, last_bucket: usize, rebin: usize) -> SamplingConfig {
+        SamplingConfig {
+            first_bucket,
+            last_bucket,
+            rebin,
+            ..Default::default()
         }
     }
 
-    #[allow(dead_code)]
-    fn remove_fpn_channels(&mut self, pad_map: &PadMap, cobo_id: &u8, asad_id: &u8, aget_id: &u8) {
-        for channel in FPN_CHANNELS {
-            self.remove_trace(pad_map, &cobo_id, &asad_id, &aget_id, &channel);
+    fn make_event() -> Event {
+        Event::new(
+            &PadMap::default(),
+            &Vec::new(),
+            MergeMode::EventId,
+            TraceOrder::HardwareAddress,
+        )
+        .unwrap()
+    }
+
+    /// Add a trace to the event, with the given samples at the start and empty buckets after them
+    fn add_trace(event: &mut Event, aget: u8, channel: u8, samples: &[i16]) -> HardwareID {
+        let hw_id = HardwareID::new(
+            &0,
+            &0,
+            &aget,
+            &channel,
+            &(aget as u64 * 100 + channel as u64),
+        );
+        let row = event.ids.len();
+        event
+            .data
+            .extend_from_slice(&[0, 0, aget as i16, channel as i16, hw_id.pad_id as i16]);
+        event.data.extend_from_slice(samples);
+        event.data.resize((row + 1) * event.row_width, 0);
+        // Samples of 0 are taken as not read
+        event.read.extend(
+            event.data[row * event.row_width..]
+                .iter()
+                .enumerate()
+                .map(|(col, sample)| col >= 5 && *sample != 0),
+        );
+        event.rows.insert(hw_id.clone(), row);
+        event.ids.push(hw_id.clone());
+        hw_id
+    }
+
+    fn get_samples(event: &Event, hw_id: &HardwareID, n_samples: usize) -> Vec<i16> {
+        event
+            .get_trace(event.rows[hw_id])
+            .iter()
+            .take(n_samples)
+            .copied()
+            .collect()
+    }
+
+    /// An AGET with four FPN traces (with empty buckets) and two signal traces, and a second AGET with no FPN traces
+    fn make_fpn_event() -> (Event, Vec<HardwareID>) {
+        let mut event = make_event();
+        let signal = add_trace(&mut event, 0, 0, &[100, 100, 100, 0]);
+        add_trace(&mut event, 0, 11, &[10, 12]);
+        let sparse = add_trace(&mut event, 0, 1, &[0, 50, 0, 0]);
+        add_trace(&mut event, 0, 22, &[11, 13]);
+        add_trace(&mut event, 0, 45, &[0, 14]);
+        add_trace(&mut event, 0, 56, &[0, 0]);
+        let other = add_trace(&mut event, 1, 0, &[100, 100, 100, 0]);
+        (event, vec![signal, sparse, other])
+    }
+
+    /// A matrix of n_rows rows of width 2, where row i is [i, -i]
+    fn make_rows(n_rows: usize) -> Vec<i16> {
+        (0..n_rows as i16).flat_map(|row| [row, -row]).collect()
+    }
+
+    #[test]
+    fn permute_rows_identity() {
+        let mut data = make_rows(4);
+        permute_rows(&mut data, &[0, 1, 2, 3], 2);
+        assert_eq!(data, make_rows(4));
+    }
+
+    #[test]
+    fn permute_rows_reversed() {
+        let mut data = make_rows(5);
+        permute_rows(&mut data, &[4, 3, 2, 1, 0], 2);
+        assert_eq!(data, vec![4, -4, 3, -3, 2, -2, 1, -1, 0, 0]);
+    }
+
+    #[test]
+    fn permute_rows_multiple_cycles() {
+        // Cycles (0 2 1), (3 4), and the fixed row 5
+        let order = [2, 0, 1, 4, 3, 5];
+        let mut data = make_rows(6);
+        permute_rows(&mut data, &order, 2);
+        let expected: Vec<i16> = order
+            .iter()
+            .flat_map(|row| [*row as i16, -(*row as i16)])
+            .collect();
+        assert_eq!(data, expected);
+    }
+
+    /// An event with traces on channels 0 to n_traces - 1, each filled with its channel number
+    fn make_filled_event(n_traces: u8) -> Event {
+        let mut event = make_event();
+        for channel in 0..n_traces {
+            add_trace(&mut event, 0, channel, &[channel as i16 + 1; 3]);
+        }
+        event
+    }
+
+    /// The channel of each row and the first sample of its trace
+    fn get_row_channels(event: &Event) -> Vec<(u8, i16)> {
+        (0..event.ids.len())
+            .map(|row| (event.ids[row].channel as u8, event.get_trace(row)[0]))
+            .collect()
+    }
+
+    #[test]
+    fn remove_first_and_last_rows() {
+        let mut event = make_filled_event(4);
+        event.remove_rows(&[true, false, false, true]);
+        assert_eq!(get_row_channels(&event), vec![(1, 2), (2, 3)]);
+        assert_eq!(event.data.len(), 2 * event.row_width);
+        assert_eq!(event.rows[&event.ids[0]], 0);
+        assert_eq!(event.rows[&event.ids[1]], 1);
+    }
+
+    #[test]
+    fn remove_every_row() {
+        let mut event = make_filled_event(3);
+        event.remove_rows(&[true, true, true]);
+        assert_eq!(event.get_number_of_traces(), 0);
+        assert!(event.data.is_empty());
+        assert!(event.rows.is_empty());
+    }
+
+    #[test]
+    fn bad_time_bucket_is_an_error() {
+        let mut frame = GrawFrame::new();
+        frame.data.push(GrawData {
+            time_bucket_id: NUMBER_OF_TIME_BUCKETS as u16,
+            ..Default::default()
+        });
+        let result = Event::new(
+            &PadMap::default(),
+            &vec![frame],
+            MergeMode::EventId,
+            TraceOrder::HardwareAddress,
+        );
+        assert!(matches!(result, Err(EventError::BadTimeBucket(512))));
+    }
+
+    #[test]
+    fn fpn_is_averaged_over_read_buckets() {
+        let (mut event, ids) = make_fpn_event();
+        event.subtract_fixed_pattern_noise(false);
+        // The FPN means are 10.5 and 13, with a mean of 11.75, so the pattern is -1.25, 1.25 and nothing after
+        assert_eq!(get_samples(&event, &ids[0], 4), vec![101, 99, 100, 0]);
+        assert_eq!(event.get_number_of_traces(), 3);
+    }
+
+    #[test]
+    fn fpn_leaves_empty_buckets_empty() {
+        let (mut event, ids) = make_fpn_event();
+        event.subtract_fixed_pattern_noise(false);
+        assert_eq!(get_samples(&event, &ids[1], 4), vec![0, 49, 0, 0]);
+    }
+
+    #[test]
+    fn fpn_skips_aget_without_fpn_traces() {
+        let (mut event, ids) = make_fpn_event();
+        event.subtract_fixed_pattern_noise(false);
+        assert_eq!(get_samples(&event, &ids[2], 4), vec![100, 100, 100, 0]);
+    }
+
+    #[test]
+    fn fpn_traces_are_kept_on_request() {
+        let (mut event, _) = make_fpn_event();
+        event.subtract_fixed_pattern_noise(false);
+        assert!(event.take_fpn_data_matrix().is_none());
+
+        let (mut event, _) = make_fpn_event();
+        event.subtract_fixed_pattern_noise(true);
+        let fpn = event.take_fpn_data_matrix().unwrap();
+        assert_eq!(fpn.shape(), &[4, NUMBER_OF_MATRIX_COLUMNS]);
+        let channels: Vec<i16> = fpn.column(3).to_vec();
+        assert_eq!(channels, vec![11, 22, 45, 56]);
+        assert_eq!(
+            fpn.row(2)
+                .iter()
+                .skip(5)
+                .take(3)
+                .copied()
+                .collect::<Vec<i16>>(),
+            vec![0, 14, 0]
+        );
+        assert_eq!(event.get_number_of_traces(), 3);
+    }
+
+    #[test]
+    fn subtracted_zero_samples_stay_read() {
+        let mut event = make_event();
+        add_trace(&mut event, 0, 0, &[10, 10, 10, 10, 30]);
+        let config = BaselineConfig {
+            n_buckets: 4,
+            ..Default::default()
+        };
+        event.subtract_baselines(&config);
+        let sparse = event.convert_to_sparse(None);
+        assert_eq!(sparse.runs, ndarray::arr2(&[[0, 0, 5, 0]]));
+        assert_eq!(sparse.samples.to_vec(), vec![0, 0, 0, 0, 20]);
+    }
+
+    #[test]
+    fn resample_crops() {
+        let mut event = make_event();
+        let hw_id = add_trace(&mut event, 0, 3, &[1, 2, 3, 4, 5, 6, 7]);
+        event.resample(&make_sampling(2, 5, 1));
+        assert_eq!(get_samples(&event, &hw_id, 4), vec![3, 4, 5, 6]);
+        let data = event.convert_to_data_matrix();
+        assert_eq!(data.shape(), &[1, 9]);
+        assert_eq!(data.row(0).to_vec(), vec![0, 0, 0, 3, 3, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn resample_drops_partial_final_bin() {
+        let mut event = make_event();
+        let hw_id = add_trace(&mut event, 0, 0, &[1, 2, 3, 4, 5, 6, 7]);
+        event.resample(&make_sampling(0, 6, 3));
+        assert_eq!(event.row_width, 7);
+        assert_eq!(get_samples(&event, &hw_id, 2), vec![2, 5]);
+    }
+
+    #[test]
+    fn resample_averages_read_buckets() {
+        let mut event = make_event();
+        let hw_id = add_trace(&mut event, 0, 0, &[10, 0, 20, 0, 0, 0, 30, 0, 0, 40, 0, 50]);
+        // A read sample of 0 is averaged like any other
+        event.read[5 + 10] = true;
+        event.resample(&make_sampling(0, 11, 3));
+        assert_eq!(get_samples(&event, &hw_id, 4), vec![15, 0, 30, 30]);
+        // The bin with no buckets read stays unread
+        let sparse = event.convert_to_sparse(None);
+        assert_eq!(sparse.runs, ndarray::arr2(&[[0, 0, 1, 0], [0, 2, 2, 1]]));
+    }
+
+    #[test]
+    fn resample_fpn_traces() {
+        let (mut event, ids) = make_fpn_event();
+        event.subtract_fixed_pattern_noise(true);
+        event.resample(&make_sampling(1, 2, 1));
+        assert_eq!(get_samples(&event, &ids[2], 2), vec![100, 100]);
+        let fpn = event.take_fpn_data_matrix().unwrap();
+        assert_eq!(fpn.shape(), &[4, event.row_width]);
+        assert_eq!(fpn.column(3).to_vec(), vec![11, 22, 45, 56]);
+        assert_eq!(
+            fpn.row(0).iter().skip(5).copied().collect::<Vec<i16>>(),
+            vec![12, 0]
+        );
+        assert_eq!(
+            fpn.row(2).iter().skip(5).copied().collect::<Vec<i16>>(),
+            vec![14, 0]
+        );
+    }
+
+    /// An event with one trace, a charge of 1000 at bucket 20 convolved with the analytic response, and the response
+    fn make_impulse_event() -> (Event, ResponseFunction) {
+        let response = ResponseFunction::analytic(502.0, 6.25).unwrap();
+        let mut samples = vec![0i16; 60];
+        for (shift, weight) in response.get_values().iter().enumerate() {
+            samples[20 + shift] = (1000.0 * weight).round() as i16;
+        }
+        let mut event = make_event();
+        add_trace(&mut event, 0, 0, &samples);
+        (event, response)
+    }
+
+    fn deconvolve_impulse(event: &mut Event, response: &ResponseFunction) -> Vec<f32> {
+        let config = DeconvolutionConfig {
+            enabled: true,
+            iterations: 200,
+            find_hits: true,
+            ..Default::default()
+        };
+        event.deconvolve(response, &config, &HitConfig::default());
+        let matrix = event.take_deconvolved_matrix().unwrap();
+        matrix.row(0).iter().skip(5).copied().collect()
+    }
+
+    #[test]
+    fn deconvolve_recovers_impulse() {
+        let (mut event, response) = make_impulse_event();
+        let deconvolved = deconvolve_impulse(&mut event, &response);
+        // Without a calibration the samples are used as they are, and the charge is kept
+        let total: f32 = deconvolved.iter().sum();
+        assert!((total - 1000.0).abs() < 10.0);
+        assert!(deconvolved[20] > 950.0);
+
+        let hits = event.get_deconvolved_hit_matrix().unwrap();
+        assert_eq!(hits.nrows(), 1);
+        assert!((hits[[0, 5]] - 20.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn deconvolve_applies_calibration() {
+        let (mut event, response) = make_impulse_event();
+        let uncalibrated = deconvolve_impulse(&mut event, &response);
+        let gain = PadGain {
+            gain: 2.0,
+            offset: 0.0,
+        };
+        event.gains = Some(
+            event
+                .ids
+                .iter()
+                .map(|hw_id| (hw_id.clone(), gain))
+                .collect(),
+        );
+        let calibrated = deconvolve_impulse(&mut event, &response);
+        for (raw, scaled) in uncalibrated.iter().zip(calibrated.iter()) {
+            assert!((2.0 * raw - scaled).abs() < 1.0e-3);
         }
     }
 }
@@ -1,68 +1,331 @@
+use std::collections::VecDeque;
+
+use super::config::{Config, DuplicatePolicy, MergeMode, TraceOrder};
 use super::error::EventBuilderError;
 use super::event::Event;
 use super::graw_frame::GrawFrame;
 use super::pad_map::PadMap;
 
+/// # BuilderStats
+/// Counts of the problems the EventBuilder recovered from during a run
+#[derive(Debug, Clone, Default)]
+pub struct BuilderStats {
+    pub late_frames: u64, // frames which arrived after their event was already completed, and were dropped
+    pub duplicate_frames: u64, // frames from a CoBo/AsAd which had already contributed to the event
+}
+
+impl BuilderStats {
+    /// Log a summary of the stats
+    pub fn log_summary(&self) {
+        if self.late_frames != 0 {
+            log::warn!(
+                "{} frames arrived after their event was completed and were dropped",
+                self.late_frames
+            );
+        }
+        if self.duplicate_frames != 0 {
+            log::warn!(
+                "{} duplicate frames were found and resolved by the duplicate policy",
+                self.duplicate_frames
+            );
+        }
+    }
+
+    /// The stats as an array of [late frames, duplicate frames]
+    pub fn get_stats_array(&self) -> [u64; 2] {
+        [self.late_frames, self.duplicate_frames]
+    }
+}
+
+/// # OpenEvent
+/// An event which is still accepting frames
+#[derive(Debug)]
+struct OpenEvent {
+    key: (u32, u64), // (epoch, event id or timestamp)
+    frames: Vec<GrawFrame>,
+}
+
 /// # EventBuilder
-/// EventBuilder takes GrawFrames and composes them into Events.
+/// EventBuilder takes GrawFrames and composes them into Events. Frames are grouped either by EventID or by timestamp,
+/// depending on the MergeMode of the Config (the Merger must be using the same mode). Frames are compared by epoch first,
+/// so a counter reset or wraparound detected by the Merger starts a new event rather than looking out of order.
+///
+/// ## Reorder Window
+/// The builder keeps up to reorder_window + 1 events open at once. A frame which belongs to any open event is added to it, so
+/// a stack which lags behind the others does not break the event. The Merger returns a frame which is out of order within its
+/// source as soon as it is read, so it arrives here after frames from later events. Once more events are open than the window allows, the oldest
+/// is completed. Frames which arrive after their event was completed are dropped and counted in the BuilderStats. With a window
+/// of 0 only the newest event is open.
+///
+/// ## Duplicates
+/// An event should only ever receive one frame from each CoBo/AsAd. A second frame is resolved by the DuplicatePolicy of the
+/// Config and counted in the BuilderStats.
 #[derive(Debug)]
 pub struct EventBuilder {
-    current_event_id: u32,
+    open_events: VecDeque<OpenEvent>, // sorted oldest to newest
+    last_completed_key: Option<(u32, u64)>,
+    reorder_window: usize,
+    merge_mode: MergeMode,
+    timestamp_tolerance: u64,
+    duplicate_policy: DuplicatePolicy,
+    trace_order: TraceOrder,
     pad_map: PadMap,
-    frame_stack: Vec<GrawFrame>,
+    stats: BuilderStats,
 }
 
 impl EventBuilder {
     /// Create a new EventBuilder. Requires a PadMap
-    pub fn new(pad_map: PadMap) -> Self {
+    pub fn new(pad_map: PadMap, config: &Config) -> Self {
         EventBuilder {
-            current_event_id: 0,
+            open_events: VecDeque::new(),
+            last_completed_key: None,
+            reorder_window: config.reorder_window,
+            merge_mode: config.merge_mode,
+            timestamp_tolerance: config.timestamp_tolerance,
+            duplicate_policy: config.duplicate_policy,
+            trace_order: config.trace_order,
             pad_map,
-            frame_stack: Vec::new(),
+            stats: BuilderStats::default(),
         }
     }
 
-    /// Add a frame to the event. If the frame does not have the same EventID as the event currently being built,
-    /// this is taken as indication that that event is complete, and a new event should be started for the frame given.
-    /// Returns a Result<Option<Event>>. If the Option is None, the event being built is not complete. If the Optiion is Some,
-    /// the event being built was completed, and a new event was started for the frame that was passed in.
+    /// Add a frame to the event it belongs to. If the frame starts a new event and there are then more open events than the
+    /// reorder window allows, the oldest event is taken as complete.
+    /// Returns a Result<Option<Event>>. If the Option is None, no event was completed. If the Option is Some,
+    /// the oldest open event was completed.
     pub fn append_frame(&mut self, frame: GrawFrame) -> Result<Option<Event>, EventBuilderError> {
-        if frame.header.event_id > self.current_event_id && self.current_event_id != 0 {
-            //event completed and start a new event.
-            let event: Event = Event::new(&self.pad_map, &self.frame_stack)?;
-            self.frame_stack.clear();
-            self.current_event_id = frame.header.event_id;
-            self.frame_stack.push(frame);
-
-            return Ok(Some(event));
-        } else if self.current_event_id == 0 {
-            // this is the first frame ever
-            self.current_event_id = frame.header.event_id;
-            self.frame_stack.push(frame);
-            return Ok(None);
-        } else if frame.header.event_id < self.current_event_id {
-            //Oops out of order
-            return Err(EventBuilderError::EventOutOfOrder(
-                frame.header.event_id,
-                self.current_event_id,
-            ));
-        } else {
+        let frame_key = self.get_frame_key(&frame);
+
+        if let Some(open) = self.open_events.iter_mut().find(|open| {
+            Self::is_same_event(
+                self.merge_mode,
+                self.timestamp_tolerance,
+                &open.key,
+                &frame_key,
+            )
+        }) {
             //Still building
-            self.frame_stack.push(frame);
+            let duplicate = open.frames.iter().position(|other| {
+                other.header.cobo_id == frame.header.cobo_id
+                    && other.header.asad_id == frame.header.asad_id
+            });
+            match (duplicate, self.duplicate_policy) {
+                (None, _) => open.frames.push(frame),
+                (Some(_), DuplicatePolicy::KeepFirst) => self.stats.duplicate_frames += 1,
+                (Some(idx), DuplicatePolicy::KeepLast) => {
+                    self.stats.duplicate_frames += 1;
+                    open.frames[idx] = frame;
+                }
+                (Some(_), DuplicatePolicy::Error) => {
+                    return Err(EventBuilderError::DuplicateFrame(
+                        frame.header.cobo_id,
+                        frame.header.asad_id,
+                        frame.header.event_id,
+                    ))
+                }
+            }
             return Ok(None);
         }
+
+        if let Some(completed_key) = self.last_completed_key {
+            if frame_key < completed_key
+                || Self::is_same_event(
+                    self.merge_mode,
+                    self.timestamp_tolerance,
+                    &completed_key,
+                    &frame_key,
+                )
+            {
+                //Too late, the event was already completed
+                log::debug!(
+                    "Dropping late frame from CoBo {} AsAd {} with event id {} timestamp {}",
+                    frame.header.cobo_id,
+                    frame.header.asad_id,
+                    frame.header.event_id,
+                    frame.header.event_time
+                );
+                self.stats.late_frames += 1;
+                return Ok(None);
+            }
+        }
+
+        //Start a new event, keeping the open events sorted
+        let position = self
+            .open_events
+            .iter()
+            .position(|open| open.key > frame_key)
+            .unwrap_or(self.open_events.len());
+        self.open_events.insert(
+            position,
+            OpenEvent {
+                key: frame_key,
+                frames: vec![frame],
+            },
+        );
+
+        if self.open_events.len() > self.reorder_window + 1 {
+            //event completed
+            return Ok(Some(self.complete_oldest_event()?));
+        }
+        Ok(None)
+    }
+
+    /// Takes all remaining open events and flushes them. Used at the end of processing a run.
+    /// Events which fail to build are logged and skipped.
+    pub fn flush_events(&mut self) -> Vec<Event> {
+        let mut events: Vec<Event> = Vec::new();
+        while !self.open_events.is_empty() {
+            match self.complete_oldest_event() {
+                Ok(event) => events.push(event),
+                Err(e) => log::error!("Could not flush event: {}", e),
+            }
+        }
+        events
     }
 
-    /// Takes any remaining frames and flushes them to an event. Used at the end of processing a run.
-    /// Returns None if there were no frames left over.
-    pub fn flush_final_event(&mut self) -> Option<Event> {
-        if self.frame_stack.len() != 0 {
-            match Event::new(&self.pad_map, &self.frame_stack) {
-                Ok(event) => Some(event),
-                Err(_) => None,
+    pub fn get_stats(&self) -> &BuilderStats {
+        &self.stats
+    }
+
+    /// Build the oldest open event. The open events must not be empty.
+    fn complete_oldest_event(&mut self) -> Result<Event, EventBuilderError> {
+        let open = self
+            .open_events
+            .pop_front()
+            .expect("EventBuilder tried to complete an event with no open events!");
+        self.last_completed_key = Some(open.key);
+        Ok(Event::new(
+            &self.pad_map,
+            &open.frames,
+            self.merge_mode,
+            self.trace_order,
+        )?)
+    }
+
+    /// The key used to order and group frames: the epoch, and either the event id or the timestamp
+    fn get_frame_key(&self, frame: &GrawFrame) -> (u32, u64) {
+        match self.merge_mode {
+            MergeMode::EventId => (frame.epoch, frame.header.event_id as u64),
+            MergeMode::Timestamp => (frame.epoch, frame.header.event_time),
+        }
+    }
+
+    /// Check if a frame with the given key belongs to the event with the given key. In timestamp mode the timestamps must agree
+    /// within the tolerance of the first frame of the event.
+    fn is_same_event(
+        merge_mode: MergeMode,
+        timestamp_tolerance: u64,
+        event_key: &(u32, u64),
+        frame_key: &(u32, u64),
+    ) -> bool {
+        match merge_mode {
+            MergeMode::EventId => event_key == frame_key,
+            MergeMode::Timestamp => {
+                event_key.0 == frame_key.0
+                    && event_key.1.abs_diff(frame_key.1) <= timestamp_tolerance
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merger::completeness::board_bit;
+    use crate::merger::frame_source::{make_test_frame, make_test_source as make_source};
+    use crate::merger::merger::Merger;
+
+    /// Build the events of a source emitting 100, 101 and a lagging source emitting 101, 100. Returns the (event id,
+    /// contributors) of each event and the stats.
+    fn build_lagging_events(reorder_window: usize) -> (Vec<(u32, u64)>, BuilderStats) {
+        let mut config = Config::default();
+        config.reorder_window = reorder_window;
+        let mut merger = Merger::from_sources(
+            vec![
+                make_source(0, 0, &[100, 101]),
+                make_source(1, 0, &[101, 100]),
+            ],
+            config.merge_mode,
+        )
+        .unwrap();
+        let mut evb = EventBuilder::new(PadMap::default(), &config);
+        let mut events = Vec::new();
+        while let Some(frame) = merger.get_next_frame().unwrap() {
+            if let Some(event) = evb.append_frame(frame).unwrap() {
+                events.push(event);
             }
-        } else {
-            None
         }
+        events.append(&mut evb.flush_events());
+        let events = events
+            .iter()
+            .map(|event| (event.event_id, event.contributors))
+            .collect();
+        (events, evb.get_stats().clone())
+    }
+
+    #[test]
+    fn late_frame_joins_open_event() {
+        let both = board_bit(0, 0) | board_bit(1, 0);
+        let (events, stats) = build_lagging_events(1);
+        assert_eq!(events, vec![(100, both), (101, both)]);
+        assert_eq!(stats.late_frames, 0);
+    }
+
+    #[test]
+    fn late_frame_is_dropped_without_window() {
+        let (events, stats) = build_lagging_events(0);
+        assert_eq!(
+            events,
+            vec![
+                (100, board_bit(0, 0)),
+                (101, board_bit(0, 0) | board_bit(1, 0))
+            ]
+        );
+        assert_eq!(stats.late_frames, 1);
+    }
+
+    /// Give the builder two frames from the same AsAd for event 5, with timestamps 1 and 2, then a frame of event 6 which
+    /// completes event 5
+    fn build_duplicate_event(
+        policy: DuplicatePolicy,
+    ) -> (Result<Option<Event>, EventBuilderError>, BuilderStats) {
+        let mut config = Config::default();
+        config.duplicate_policy = policy;
+        let mut evb = EventBuilder::new(PadMap::default(), &config);
+        let mut first = make_test_frame(0, 0, 5);
+        first.header.event_time = 1;
+        let mut second = make_test_frame(0, 0, 5);
+        second.header.event_time = 2;
+        assert!(evb.append_frame(first).unwrap().is_none());
+        if let Err(e) = evb.append_frame(second) {
+            return (Err(e), evb.get_stats().clone());
+        }
+        let result = evb.append_frame(make_test_frame(0, 0, 6));
+        (result, evb.get_stats().clone())
+    }
+
+    #[test]
+    fn duplicate_keep_first() {
+        let (result, stats) = build_duplicate_event(DuplicatePolicy::KeepFirst);
+        let event = result.unwrap().unwrap();
+        assert_eq!((event.event_id, event.timestamp), (5, 1));
+        assert_eq!(stats.duplicate_frames, 1);
+    }
+
+    #[test]
+    fn duplicate_keep_last() {
+        let (result, stats) = build_duplicate_event(DuplicatePolicy::KeepLast);
+        let event = result.unwrap().unwrap();
+        assert_eq!((event.event_id, event.timestamp), (5, 2));
+        assert_eq!(stats.duplicate_frames, 1);
+    }
+
+    #[test]
+    fn duplicate_error() {
+        let (result, _) = build_duplicate_event(DuplicatePolicy::Error);
+        assert!(matches!(
+            result,
+            Err(EventBuilderError::DuplicateFrame(0, 0, 5))
+        ));
     }
 }
@@ -0,0 +1,209 @@
+use fxhash::FxHashMap;
+
+use super::completeness::board_bit;
+use super::config::FilterConfig;
+use super::constants::NUMBER_OF_ASADS;
+use super::event::Event;
+
+/// # EventFilter
+/// Decides which events are written to disk, using the criteria of a FilterConfig. Events are checked against every criterion,
+/// so an event can be rejected by more than one; the number of rejections per criterion is kept for the run summary.
+///
+/// The coincidence criterion needs the V977 register of each event, which comes from the FRIBDAQ physics data. The registers are
+/// recorded while the evt data is processed, and are matched to GET events by event id. Events with no matching physics event
+/// are rejected by this criterion.
+#[derive(Debug, Clone)]
+pub struct EventFilter {
+    config: FilterConfig,
+    coincidences: FxHashMap<u64, u16>,
+    accepted: u64,
+    rejected: u64,
+    rejected_by: [u64; 4], // [min pads, required cobos, coincidence, min total charge]
+}
+
+impl EventFilter {
+    pub fn new(config: &FilterConfig) -> Self {
+        EventFilter {
+            config: config.clone(),
+            coincidences: FxHashMap::default(),
+            accepted: 0,
+            rejected: 0,
+            rejected_by: [0; 4],
+        }
+    }
+
+    /// Record the V977 coincidence register of a FRIBDAQ physics event. Only kept if the coincidence criterion is used.
+    pub fn record_coincidence(&mut self, event_number: u64, register: u16) {
+        if self.config.coincidence_mask.is_some() {
+            self.coincidences.insert(event_number, register);
+        }
+    }
+
+    /// Check an event against every criterion. Returns true if the event should be written.
+    pub fn accept(&mut self, event: &Event) -> bool {
+        let results = [
+            self.config
+                .min_pads
+                .is_none_or(|min| event.get_number_of_traces() >= min),
+            self.config.required_cobos.iter().all(|cobo| {
+                let cobo_mask =
+                    (0..NUMBER_OF_ASADS).fold(0, |mask, asad| mask | board_bit(*cobo, asad));
+                event.contributors & cobo_mask != 0
+            }),
+            self.config.coincidence_mask.is_none_or(|mask| {
+                match self.coincidences.get(&(event.event_id as u64)) {
+                    Some(register) => register & mask == mask,
+                    None => false,
+                }
+            }),
+            self.config
+                .min_total_charge
+                .is_none_or(|min| event.get_total_charge() >= min),
+        ];
+
+        for (count, passed) in self.rejected_by.iter_mut().zip(results.iter()) {
+            if !passed {
+                *count += 1;
+            }
+        }
+        if results.iter().all(|passed| *passed) {
+            self.accepted += 1;
+            true
+        } else {
+            self.rejected += 1;
+            false
+        }
+    }
+
+    /// Log a summary of the accepted and rejected events
+    pub fn log_summary(&self) {
+        log::info!(
+            "Event filter accepted {} events and rejected {} events",
+            self.accepted,
+            self.rejected
+        );
+        if self.rejected != 0 {
+            log::info!(
+                "Rejections by criterion -- min pads: {} required CoBos: {} coincidence: {} min total charge: {}",
+                self.rejected_by[0],
+                self.rejected_by[1],
+                self.rejected_by[2],
+                self.rejected_by[3]
+            );
+        }
+    }
+
+    /// The stats as an array of [accepted, rejected, rejected by min pads, rejected by required CoBos,
+    /// rejected by coincidence, rejected by min total charge]
+    pub fn get_stats_array(&self) -> [u64; 6] {
+        [
+            self.accepted,
+            self.rejected,
+            self.rejected_by[0],
+            self.rejected_by[1],
+            self.rejected_by[2],
+            self.rejected_by[3],
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merger::config::{MergeMode, TraceOrder};
+    use crate::merger::frame_source::make_test_frame;
+    use crate::merger::graw_frame::GrawData;
+    use crate::merger::pad_map::PadMap;
+
+    /// An event 7 from CoBo 1 with a trace on channels 0 and 1 of AGET 0, each with samples 100 and 50
+    fn make_event() -> Event {
+        let pad_map = PadMap::from_rows(&[[1, 0, 0, 0, 10], [1, 0, 0, 1, 11]]);
+        let mut frame = make_test_frame(1, 0, 7);
+        for channel in 0..2 {
+            for (time_bucket_id, sample) in [(0, 100), (1, 50)] {
+                frame.data.push(GrawData {
+                    aget_id: 0,
+                    channel,
+                    time_bucket_id,
+                    sample,
+                });
+            }
+        }
+        Event::new(
+            &pad_map,
+            &vec![frame],
+            MergeMode::EventId,
+            TraceOrder::HardwareAddress,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn min_pads_cut() {
+        let mut filter = EventFilter::new(&FilterConfig {
+            min_pads: Some(2),
+            ..Default::default()
+        });
+        assert!(filter.accept(&make_event()));
+        let mut filter = EventFilter::new(&FilterConfig {
+            min_pads: Some(3),
+            ..Default::default()
+        });
+        assert!(!filter.accept(&make_event()));
+        assert_eq!(filter.get_stats_array(), [0, 1, 1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn required_cobos_cut() {
+        let mut filter = EventFilter::new(&FilterConfig {
+            required_cobos: vec![1],
+            ..Default::default()
+        });
+        assert!(filter.accept(&make_event()));
+        let mut filter = EventFilter::new(&FilterConfig {
+            required_cobos: vec![0, 1],
+            ..Default::default()
+        });
+        assert!(!filter.accept(&make_event()));
+        assert_eq!(filter.get_stats_array(), [0, 1, 0, 1, 0, 0]);
+    }
+
+    #[test]
+    fn coincidence_cut() {
+        let mut filter = EventFilter::new(&FilterConfig {
+            coincidence_mask: Some(0x3),
+            ..Default::default()
+        });
+        filter.record_coincidence(7, 0x1);
+        assert!(!filter.accept(&make_event()));
+        filter.record_coincidence(7, 0x7);
+        assert!(filter.accept(&make_event()));
+        assert_eq!(filter.get_stats_array(), [1, 1, 0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn missing_coincidence_is_rejected() {
+        let mut filter = EventFilter::new(&FilterConfig {
+            coincidence_mask: Some(0x1),
+            ..Default::default()
+        });
+        filter.record_coincidence(8, 0x1);
+        assert!(!filter.accept(&make_event()));
+        assert_eq!(filter.get_stats_array(), [0, 1, 0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn min_total_charge_cut() {
+        let mut filter = EventFilter::new(&FilterConfig {
+            min_total_charge: Some(300),
+            ..Default::default()
+        });
+        assert!(filter.accept(&make_event()));
+        let mut filter = EventFilter::new(&FilterConfig {
+            min_total_charge: Some(301),
+            ..Default::default()
+        });
+        assert!(!filter.accept(&make_event()));
+        assert_eq!(filter.get_stats_array(), [0, 1, 0, 0, 0, 1]);
+    }
+}
@@ -0,0 +1,225 @@
+use std::fmt::Write as FmtWrite;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use super::channel_mask::load_masked_pad_map;
+use super::config::Config;
+use super::error::{EventRangeError, ProcessorError};
+use super::event::Event;
+use super::event_builder::EventBuilder;
+use super::hdf_writer::HDFWriter;
+use super::merger::Merger;
+
+/// # EventRange
+/// An inclusive range of event ids. A single event is a range where first == last.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EventRange {
+    pub first: u32,
+    pub last: u32,
+}
+
+impl TryFrom<&str> for EventRange {
+    type Error = EventRangeError;
+    /// Parse either a single event id (`123`) or an inclusive range (`100-200`). Reversed ranges (`200-100`) are rejected.
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.split_once('-') {
+            Some((first, last)) => {
+                let range = EventRange {
+                    first: first.trim().parse()?,
+                    last: last.trim().parse()?,
+                };
+                if range.first > range.last {
+                    return Err(EventRangeError::Reversed(range.first, range.last));
+                }
+                Ok(range)
+            }
+            None => {
+                let id: u32 = value.trim().parse()?;
+                Ok(EventRange {
+                    first: id,
+                    last: id,
+                })
+            }
+        }
+    }
+}
+
+/// Sort the ranges and merge the ones which overlap or touch, so that each event is read once and each seek moves forward
+/// through the files
+fn merge_ranges(ranges: &[EventRange]) -> Vec<EventRange> {
+    let mut sorted = ranges.to_vec();
+    sorted.sort_by_key(|range| range.first);
+    let mut merged: Vec<EventRange> = Vec::new();
+    for range in sorted {
+        match merged.last_mut() {
+            Some(last) if range.first <= last.last.saturating_add(1) => {
+                last.last = last.last.max(range.last)
+            }
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+/// Extract the requested events of a run directly from the raw .graw data and write them to the output file.
+/// The frame index of each AsadStack is used to jump to the start of each range, so only the matching frames are read.
+/// Overlapping ranges are merged, so each event is written once. The output format is chosen by the extension of the output
+/// path: `.json` writes JSON, anything else writes HDF5 using the event layout of a full conversion, along with the meta, file
+/// information and channel mask datasets. No trace processing is applied. Returns the number of events written.
+pub fn extract_events(
+    config: &Config,
+    run_number: i32,
+    ranges: &[EventRange],
+    output_path: &Path,
+) -> Result<u64, ProcessorError> {
+    let (pad_map, mask) = load_masked_pad_map(config)?;
+    let mut merger = Merger::new(config, run_number)?;
+    merger.build_frame_indices()?;
+
+    let mut events: Vec<Event> = Vec::new();
+    for range in merge_ranges(ranges).iter() {
+        log::info!("Extracting events {} to {}...", range.first, range.last);
+        merger.seek_to_event(range.first)?;
+        let mut evb = EventBuilder::new(pad_map.clone(), config);
+        while let Some(frame) = merger.get_next_frame()? {
+            if frame.header.event_id > range.last {
+                break;
+            }
+            if let Some(event) = evb.append_frame(frame)? {
+                events.push(event);
+            }
+        }
+        events.append(&mut evb.flush_events());
+    }
+
+    let n_events = events.len() as u64;
+    let is_json = match output_path.extension() {
+        Some(ext) => ext == "json",
+        None => false,
+    };
+    if is_json {
+        write_json(events, run_number, output_path)?;
+    } else {
+        let writer = write_hdf(events, config, output_path)?;
+        if let Err(e) = writer.write_fileinfo(&merger) {
+            log::error!("Could not write the file information: {}", e);
+        }
+        if let Some(mask) = &mask {
+            if let Err(e) = writer.write_channel_mask(mask) {
+                log::error!("Could not write the channel mask: {}", e);
+            }
+        }
+    }
+    log::info!("Extracted {} events to {}", n_events, output_path.display());
+    Ok(n_events)
+}
+
+/// Write events and the meta information to an HDF5 file, returning the writer so that the file information can be added.
+/// No hits are found during extraction, so the hit options are not applied and the traces are always written.
+fn write_hdf(events: Vec<Event>, config: &Config, path: &Path) -> Result<HDFWriter, hdf5::Error> {
+    let mut writer = HDFWriter::new(path)?;
+    writer.set_sparse(&config.sparse);
+    for (event_counter, event) in events.into_iter().enumerate() {
+        writer.write_event(event, &(event_counter as u64))?;
+    }
+    writer.write_meta()?;
+    Ok(writer)
+}
+
+/// Write events to a JSON file. Each event holds its header information and the rows of its data matrix
+/// (CoBo, AsAd, AGET, Channel, Pad, followed by the trace).
+fn write_json(events: Vec<Event>, run_number: i32, path: &Path) -> Result<(), std::io::Error> {
+    let mut json = String::new();
+    // Writing to a String cannot fail
+    let _ = write!(json, "{{\"run\":{},\"events\":[", run_number);
+    for (idx, event) in events.into_iter().enumerate() {
+        if idx != 0 {
+            json.push(',');
+        }
+        let _ = write!(
+            json,
+            "{{\"event_id\":{},\"timestamp\":{},\"timestampother\":{},\"traces\":[",
+            event.event_id, event.timestamp, event.timestampother
+        );
+        let data_matrix = event.convert_to_data_matrix();
+        for (row_idx, row) in data_matrix.outer_iter().enumerate() {
+            if row_idx != 0 {
+                json.push(',');
+            }
+            json.push('[');
+            for (col_idx, value) in row.iter().enumerate() {
+                if col_idx != 0 {
+                    json.push(',');
+                }
+                let _ = write!(json, "{}", value);
+            }
+            json.push(']');
+        }
+        json.push_str("]}");
+    }
+    json.push_str("]}\n");
+
+    let mut file = File::create(path)?;
+    file.write_all(json.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merger::frame_source::make_test_frame;
+    use crate::merger::pad_map::PadMap;
+
+    fn range(first: u32, last: u32) -> EventRange {
+        EventRange { first, last }
+    }
+
+    #[test]
+    fn parse_ranges() {
+        assert_eq!(EventRange::try_from("12").unwrap(), range(12, 12));
+        assert_eq!(EventRange::try_from(" 100 - 200").unwrap(), range(100, 200));
+        assert!(matches!(
+            EventRange::try_from("200-100"),
+            Err(EventRangeError::Reversed(200, 100))
+        ));
+        assert!(matches!(
+            EventRange::try_from("a-100"),
+            Err(EventRangeError::BadEventId(_))
+        ));
+    }
+
+    #[test]
+    fn merge_overlapping_ranges() {
+        let merged = merge_ranges(&[
+            range(150, 160),
+            range(300, 300),
+            range(100, 200),
+            range(201, 210),
+        ]);
+        assert_eq!(merged, vec![range(100, 210), range(300, 300)]);
+    }
+
+    #[test]
+    fn hits_do_not_drop_traces() {
+        let mut config = Config::default();
+        config.hits.enabled = true;
+        config.hits.write_traces = false;
+        let mut evb = EventBuilder::new(PadMap::default(), &config);
+        evb.append_frame(make_test_frame(0, 0, 1)).unwrap();
+        let events = evb.flush_events();
+        assert_eq!(events.len(), 1);
+
+        let path =
+            std::env::temp_dir().join(format!("extract_hits_test_{}.h5", std::process::id()));
+        drop(write_hdf(events, &config, &path).unwrap());
+        let group = hdf5::File::open(&path).unwrap().group("get").unwrap();
+        let (has_traces, has_hits) = (
+            group.link_exists("evt0_data"),
+            group.link_exists("evt0_hits"),
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert!(has_traces);
+        assert!(!has_hits);
+    }
+}
@@ -0,0 +1,228 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use super::error::FrameIndexError;
+use super::graw_file::GrawFile;
+
+const INDEX_MAGIC: &[u8; 4] = b"GIDX";
+const INDEX_VERSION: u32 = 1;
+const ENTRY_SIZE: u64 = 28; // bytes per entry in the sidecar
+pub const INDEX_EXTENSION: &str = "gidx";
+
+/// # FrameIndexEntry
+/// The location of a single GrawFrame on disk, along with the event information needed to find it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FrameIndexEntry {
+    pub event_id: u32,
+    pub event_time: u64,
+    pub file_index: u32, // index into the file list of the FrameIndex
+    pub offset: u64,     // position of the frame header in the file, in bytes
+    pub size: u32,       // size of the frame in bytes (including padding)
+}
+
+/// # FrameIndex
+/// A FrameIndex records where every frame of an AsadStack lives on disk. It is built by scanning the headers of each .graw file
+/// once, and is then stored in a sidecar file next to the data so that later reads can jump directly to a given event instead of
+/// merging from the start of the run.
+///
+/// ## Format
+/// The sidecar is little-endian binary: the magic bytes `GIDX`, a u32 version, the number of files (u32) followed by each file name
+/// (u32 length + bytes) and size in bytes (u64), then the number of entries (u64) followed by each entry
+/// (event_id u32, event_time u64, file_index u32, offset u64, size u32). File sizes are used to detect stale sidecars.
+///
+/// Entries are stored in file order, and event ids are expected to increase within each file, so lookups are binary searches.
+#[derive(Debug, Clone, Default)]
+pub struct FrameIndex {
+    files: Vec<(PathBuf, u64)>,
+    entries: Vec<FrameIndexEntry>,
+}
+
+impl FrameIndex {
+    /// Build an index by scanning each of the given files in order
+    pub fn build(file_list: &[PathBuf]) -> Result<Self, FrameIndexError> {
+        let mut index = FrameIndex::default();
+        for (file_index, path) in file_list.iter().enumerate() {
+            let mut file = GrawFile::new(path)?;
+            index
+                .entries
+                .append(&mut file.scan_frame_index(file_index as u32)?);
+            index
+                .files
+                .push((file_name_of(path), file.get_size_bytes()));
+        }
+        Ok(index)
+    }
+
+    /// Read an index from a sidecar file
+    pub fn read(path: &Path) -> Result<Self, FrameIndexError> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic: [u8; 4] = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != INDEX_MAGIC || reader.read_u32::<LittleEndian>()? != INDEX_VERSION {
+            return Err(FrameIndexError::BadFileFormat(path.to_path_buf()));
+        }
+
+        let mut index = FrameIndex::default();
+        let n_files = reader.read_u32::<LittleEndian>()?;
+        for _ in 0..n_files {
+            let name_length = reader.read_u32::<LittleEndian>()? as usize;
+            let mut name: Vec<u8> = vec![0; name_length];
+            reader.read_exact(&mut name)?;
+            let name = String::from_utf8(name)
+                .map_err(|_| FrameIndexError::BadFileFormat(path.to_path_buf()))?;
+            let size = reader.read_u64::<LittleEndian>()?;
+            index.files.push((PathBuf::from(name), size));
+        }
+
+        let n_entries = reader.read_u64::<LittleEndian>()?;
+        // Don't trust the count of a truncated or corrupt file with the allocation
+        let remaining = reader
+            .get_ref()
+            .metadata()?
+            .len()
+            .saturating_sub(reader.stream_position()?);
+        if n_entries > remaining / ENTRY_SIZE {
+            return Err(FrameIndexError::BadFileFormat(path.to_path_buf()));
+        }
+        index.entries.reserve(n_entries as usize);
+        for _ in 0..n_entries {
+            index.entries.push(FrameIndexEntry {
+                event_id: reader.read_u32::<LittleEndian>()?,
+                event_time: reader.read_u64::<LittleEndian>()?,
+                file_index: reader.read_u32::<LittleEndian>()?,
+                offset: reader.read_u64::<LittleEndian>()?,
+                size: reader.read_u32::<LittleEndian>()?,
+            });
+        }
+        Ok(index)
+    }
+
+    /// Write the index to a sidecar file
+    pub fn write(&self, path: &Path) -> Result<(), FrameIndexError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(INDEX_MAGIC)?;
+        writer.write_u32::<LittleEndian>(INDEX_VERSION)?;
+        writer.write_u32::<LittleEndian>(self.files.len() as u32)?;
+        for (name, size) in self.files.iter() {
+            let name_bytes = name.to_string_lossy();
+            writer.write_u32::<LittleEndian>(name_bytes.len() as u32)?;
+            writer.write_all(name_bytes.as_bytes())?;
+            writer.write_u64::<LittleEndian>(*size)?;
+        }
+        writer.write_u64::<LittleEndian>(self.entries.len() as u64)?;
+        for entry in self.entries.iter() {
+            writer.write_u32::<LittleEndian>(entry.event_id)?;
+            writer.write_u64::<LittleEndian>(entry.event_time)?;
+            writer.write_u32::<LittleEndian>(entry.file_index)?;
+            writer.write_u64::<LittleEndian>(entry.offset)?;
+            writer.write_u32::<LittleEndian>(entry.size)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Check that the index was built from exactly this list of files (same names, same sizes)
+    pub fn is_valid_for(&self, file_list: &[PathBuf]) -> bool {
+        if self.files.len() != file_list.len() {
+            return false;
+        }
+        self.files
+            .iter()
+            .zip(file_list.iter())
+            .all(|((name, size), path)| match path.metadata() {
+                Ok(meta) => *name == file_name_of(path) && *size == meta.len(),
+                Err(_) => false,
+            })
+    }
+
+    /// Find the first frame (in file order) whose event id is greater than or equal to the requested one.
+    pub fn find_first_frame(&self, event_id: u32) -> Option<&FrameIndexEntry> {
+        (0..self.files.len() as u32)
+            .find_map(|file_index| self.find_first_frame_in_file(file_index, event_id))
+    }
+
+    /// The first frame of a given file whose event id is greater than or equal to the requested one.
+    pub fn find_first_frame_in_file(
+        &self,
+        file_index: u32,
+        event_id: u32,
+    ) -> Option<&FrameIndexEntry> {
+        let entries = self.get_file_entries(file_index);
+        entries.get(entries.partition_point(|entry| entry.event_id < event_id))
+    }
+
+    /// The entries of a single file
+    fn get_file_entries(&self, file_index: u32) -> &[FrameIndexEntry] {
+        let start = self
+            .entries
+            .partition_point(|entry| entry.file_index < file_index);
+        let end = self
+            .entries
+            .partition_point(|entry| entry.file_index <= file_index);
+        &self.entries[start..end]
+    }
+}
+
+/// Only the file name is stored so that the data and its sidecar can be moved together
+fn file_name_of(path: &Path) -> PathBuf {
+    match path.file_name() {
+        Some(name) => PathBuf::from(name),
+        None => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_index() -> FrameIndex {
+        let mut index = FrameIndex::default();
+        for (file_index, ids) in [[1, 2, 4], [5, 7, 9]].iter().enumerate() {
+            index
+                .files
+                .push((PathBuf::from(format!("file{}.graw", file_index)), 0));
+            for (n, event_id) in ids.iter().enumerate() {
+                index.entries.push(FrameIndexEntry {
+                    event_id: *event_id,
+                    event_time: 0,
+                    file_index: file_index as u32,
+                    offset: n as u64 * 100,
+                    size: 100,
+                });
+            }
+        }
+        index
+    }
+
+    #[test]
+    fn find_first_frame_searches_each_file() {
+        let index = make_index();
+        assert_eq!(index.find_first_frame(0).unwrap().event_id, 1);
+        assert_eq!(index.find_first_frame(3).unwrap().event_id, 4);
+        assert_eq!(index.find_first_frame(5).unwrap().event_id, 5);
+        assert_eq!(index.find_first_frame(8).unwrap().event_id, 9);
+        assert!(index.find_first_frame(10).is_none());
+        assert_eq!(index.find_first_frame_in_file(1, 0).unwrap().event_id, 5);
+        assert!(index.find_first_frame_in_file(0, 5).is_none());
+        assert!(index.find_first_frame_in_file(2, 0).is_none());
+    }
+
+    #[test]
+    fn read_round_trips_and_rejects_truncated_files() {
+        let index = make_index();
+        let path =
+            std::env::temp_dir().join(format!("frame_index_test_{}.gidx", std::process::id()));
+        index.write(&path).unwrap();
+        let read = FrameIndex::read(&path).unwrap();
+        assert_eq!(read.entries, index.entries);
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() - ENTRY_SIZE as usize]).unwrap();
+        let truncated = FrameIndex::read(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(truncated, Err(FrameIndexError::BadFileFormat(_))));
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::path::PathBuf;
+
+use super::asad_stack::AsadStack;
+use super::error::FrameSourceError;
+use super::graw_frame::{FrameMetadata, GrawFrame};
+
+/// # FrameSource
+/// A FrameSource provides the ordered stream of GrawFrames from a single AsAd on a CoBo. The Merger merges any mix of
+/// FrameSources, so data does not need to come from .graw files on disk: network streams, synthetic generators, or
+/// in-memory fixtures can all be merged by implementing this trait.
+///
+/// # IMPORTANT
+/// As with the AsadStack, the metadata for the next frame will *always* be queried before the next frame is retrieved.
+pub trait FrameSource: Debug {
+    /// Query the metadata of the next frame without consuming it. If the Option is None, the source has run out of data.
+    fn get_next_frame_metadata(&mut self) -> Result<Option<FrameMetadata>, FrameSourceError>;
+
+    /// Get the next frame
+    fn get_next_frame(&mut self) -> Result<GrawFrame, FrameSourceError>;
+
+    fn get_cobo_number(&self) -> i32;
+
+    fn get_asad_number(&self) -> i32;
+
+    /// The total size of the data in bytes, used for progress reporting. Zero if unknown.
+    fn get_size_bytes(&self) -> u64 {
+        0
+    }
+
+    /// The files read by this source and their sizes in bytes, written to the output as metadata. Empty if the source
+    /// does not read files.
+    fn get_file_list(&self) -> Vec<(PathBuf, u64)> {
+        Vec::new()
+    }
+
+    /// Prepare the source for seeking (i.e. load or build a frame index). Does nothing by default.
+    fn build_frame_index(&mut self) -> Result<(), FrameSourceError> {
+        Ok(())
+    }
+
+    /// Move to the first frame with an event id greater than or equal to the requested one. Returns false if the source
+    /// did not move, either because it does not support seeking or because it has no such frame.
+    fn seek_to_event(&mut self, _event_id: u32) -> Result<bool, FrameSourceError> {
+        Ok(false)
+    }
+}
+
+impl FrameSource for AsadStack {
+    fn get_next_frame_metadata(&mut self) -> Result<Option<FrameMetadata>, FrameSourceError> {
+        Ok(AsadStack::get_next_frame_metadata(self)?)
+    }
+
+    fn get_next_frame(&mut self) -> Result<GrawFrame, FrameSourceError> {
+        Ok(AsadStack::get_next_frame(self)?)
+    }
+
+    fn get_cobo_number(&self) -> i32 {
+        *AsadStack::get_cobo_number(self)
+    }
+
+    fn get_asad_number(&self) -> i32 {
+        *AsadStack::get_asad_number(self)
+    }
+
+    fn get_size_bytes(&self) -> u64 {
+        *self.get_stack_size_bytes()
+    }
+
+    fn get_file_list(&self) -> Vec<(PathBuf, u64)> {
+        self.get_file_list_ref()
+            .iter()
+            .map(|path| {
+                let size = match path.metadata() {
+                    Ok(meta) => meta.len(),
+                    Err(_) => 0,
+                };
+                (path.clone(), size)
+            })
+            .collect()
+    }
+
+    fn build_frame_index(&mut self) -> Result<(), FrameSourceError> {
+        Ok(self.load_or_build_frame_index()?)
+    }
+
+    fn seek_to_event(&mut self, event_id: u32) -> Result<bool, FrameSourceError> {
+        Ok(AsadStack::seek_to_event(self, event_id)?)
+    }
+}
+
+/// # MemorySource
+/// A FrameSource which serves frames held in memory. Useful for test fixtures and synthetic data.
+#[cfg_attr(not(test), allow(dead_code))]
+#[derive(Debug)]
+pub struct MemorySource {
+    cobo_number: i32,
+    asad_number: i32,
+    frames: VecDeque<GrawFrame>,
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+impl MemorySource {
+    /// Create a new source. The frames should already be in order.
+    pub fn new(cobo_number: i32, asad_number: i32, frames: Vec<GrawFrame>) -> Self {
+        MemorySource {
+            cobo_number,
+            asad_number,
+            frames: frames.into(),
+        }
+    }
+}
+
+impl FrameSource for MemorySource {
+    fn get_next_frame_metadata(&mut self) -> Result<Option<FrameMetadata>, FrameSourceError> {
+        Ok(self
+            .frames
+            .front()
+            .map(|frame| FrameMetadata::from(frame.header.clone())))
+    }
+
+    fn get_next_frame(&mut self) -> Result<GrawFrame, FrameSourceError> {
+        match self.frames.pop_front() {
+            Some(frame) => Ok(frame),
+            None => Err(FrameSourceError::SourceError(String::from(
+                "MemorySource has no frames left",
+            ))),
+        }
+    }
+
+    fn get_cobo_number(&self) -> i32 {
+        self.cobo_number
+    }
+
+    fn get_asad_number(&self) -> i32 {
+        self.asad_number
+    }
+
+    fn seek_to_event(&mut self, event_id: u32) -> Result<bool, FrameSourceError> {
+        while let Some(frame) = self.frames.front() {
+            if frame.header.event_id >= event_id {
+                return Ok(true);
+            }
+            self.frames.pop_front();
+        }
+        Ok(false)
+    }
+}
+
+/// Make an empty frame for tests. The timestamp is 1000 times the event id.
+#[cfg(test)]
+pub fn make_test_frame(cobo: u8, asad: u8, event_id: u32) -> GrawFrame {
+    let mut frame = GrawFrame::new();
+    frame.header.cobo_id = cobo;
+    frame.header.asad_id = asad;
+    frame.header.event_id = event_id;
+    frame.header.event_time = event_id as u64 * 1000;
+    frame
+}
+
+/// Make a MemorySource for tests, serving an empty frame for each event id in the given order
+#[cfg(test)]
+pub fn make_test_source(cobo: u8, asad: u8, event_ids: &[u32]) -> Box<dyn FrameSource> {
+    let frames = event_ids
+        .iter()
+        .map(|event_id| make_test_frame(cobo, asad, *event_id))
+        .collect();
+    Box::new(MemorySource::new(cobo as i32, asad as i32, frames))
+}
@@ -1,9 +1,10 @@
 use std::fs::File;
-use std::io::{Cursor, Read, Seek};
+use std::io::{Cursor, Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 
 use super::constants::*;
 use super::error::GrawFileError;
+use super::frame_index::{FrameIndex, FrameIndexEntry};
 use super::graw_frame::{FrameMetadata, GrawFrame, GrawFrameHeader};
 
 /// # GrawFile
@@ -79,6 +80,68 @@ impl GrawFile {
         Ok(self.next_frame_metadata.clone())
     }
 
+    /// Scan the headers of every frame in the file, recording where each frame is located. Frames are skipped over rather than
+    /// parsed, so this is much cheaper than reading the file. The file is returned to its start once the scan is complete.
+    pub fn scan_frame_index(
+        &mut self,
+        file_index: u32,
+    ) -> Result<Vec<FrameIndexEntry>, GrawFileError> {
+        let mut entries: Vec<FrameIndexEntry> = Vec::new();
+        self.seek_to_offset(0)?;
+        loop {
+            let offset = self.file_handle.stream_position()?;
+            let header = match self.get_next_frame_header() {
+                Ok(header) => header,
+                Err(GrawFileError::EndOfFile) => break,
+                Err(e) => return Err(e),
+            };
+            let size = header.frame_size * SIZE_UNIT;
+            if size == 0 || offset + size as u64 > self.size_bytes {
+                log::warn!(
+                    "File {} has a truncated or corrupt frame at byte {}. Frames past this point will not be indexed.",
+                    self.file_path.display(),
+                    offset
+                );
+                break;
+            }
+            entries.push(FrameIndexEntry {
+                event_id: header.event_id,
+                event_time: header.event_time,
+                file_index,
+                offset,
+                size,
+            });
+            self.file_handle
+                .seek(SeekFrom::Start(offset + size as u64))?;
+        }
+        self.seek_to_offset(0)?;
+        Ok(entries)
+    }
+
+    /// Move the read position to the given byte offset. The offset must be the start of a frame header.
+    pub fn seek_to_offset(&mut self, offset: u64) -> Result<(), GrawFileError> {
+        self.file_handle.seek(SeekFrom::Start(offset))?;
+        self.next_frame_metadata = FrameMetadata::default();
+        self.is_eof = false;
+        Ok(())
+    }
+
+    /// Use the index to move the read position to the first frame in this file with an event id greater than or equal to the
+    /// requested one. Returns false if there is no such frame in this file.
+    pub fn seek_to_event(
+        &mut self,
+        index: &FrameIndex,
+        file_index: u32,
+        event_id: u32,
+    ) -> Result<bool, GrawFileError> {
+        if let Some(entry) = index.find_first_frame_in_file(file_index, event_id) {
+            self.seek_to_offset(entry.offset)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
     /// Check to see if the file has ended
     pub fn is_eof(&self) -> &bool {
         &self.is_eof
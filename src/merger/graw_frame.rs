@@ -34,7 +34,7 @@ impl GrawData {
         if self.channel > NUMBER_OF_CHANNELS {
             return Err(GrawDataError::BadChannel(self.channel));
         }
-        if (self.time_bucket_id as u32) > NUMBER_OF_TIME_BUCKETS {
+        if (self.time_bucket_id as u32) >= NUMBER_OF_TIME_BUCKETS {
             return Err(GrawDataError::BadTimeBucket(self.time_bucket_id));
         }
 
@@ -183,12 +183,16 @@ impl GrawFrameHeader {
 /// event. GrawFrames are sized by 256 bit chunking. The header comprises one 256 bit chunks, and the body can contain several 256 bit chunks.
 /// ## Note
 /// Using 256 bit sizing is interesting because it often results in padding in both the body and the header. (It is done for performance reasons in the acquisition)
+///
+/// The epoch is not part of the data on disk. It is assigned by the Merger, and counts the number of event counter resets (or wraparounds)
+/// seen before this frame.
 #[derive(Debug)]
 pub struct GrawFrame {
     pub header: GrawFrameHeader,
     hit_patterns: Vec<BitVec<u8>>,
     multiplicity: Vec<u16>,
     pub data: Vec<GrawData>,
+    pub epoch: u32,
 }
 
 impl TryFrom<Vec<u8>> for GrawFrame {
@@ -227,6 +231,7 @@ impl GrawFrame {
             hit_patterns: vec![],
             multiplicity: vec![],
             data: vec![],
+            epoch: 0,
         }
     }
 
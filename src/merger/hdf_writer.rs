@@ -4,9 +4,19 @@ use std::error::Error;
 use std::os::unix::prelude::OsStrExt;
 use std::path::Path;
 
+use super::calibration::GainCalibration;
+use super::channel_mask::ChannelMask;
+use super::completeness::CompletenessCounter;
+use super::config::{EventWindow, HitConfig, SamplingConfig, SparseConfig};
+use super::deconvolution::ResponseFunction;
 use super::event::Event;
+use super::event_builder::BuilderStats;
+use super::event_filter::EventFilter;
 use super::merger::Merger;
+use super::pedestal::PedestalTable;
 use super::ring_item::{PhysicsItem, RunInfo, ScalersItem};
+use super::saturation::SaturationCounter;
+use super::sparse::SparseTraces;
 
 const GROUP_NAME: &str = "get";
 const META_NAME: &str = "meta";
@@ -24,6 +34,10 @@ pub struct HDFWriter {
     group: hdf5::Group,
     meta: hdf5::Group,
     meta_data: [u64; 4],
+    sparse: SparseConfig,
+    write_hits: bool,
+    write_traces: bool,
+    sampling: SamplingConfig,
     frib: hdf5::Group,
     evt: hdf5::Group,
     scaler: hdf5::Group,
@@ -44,18 +58,90 @@ impl HDFWriter {
             group,
             meta,
             meta_data,
+            sparse: SparseConfig::default(),
+            write_hits: false,
+            write_traces: true,
+            sampling: SamplingConfig::default(),
             frib,
             evt,
             scaler,
         })
     }
 
-    /// Write an event, where the event is converted into a data matrix
-    pub fn write_event(&mut self, event: Event, event_counter: &u64) -> Result<(), hdf5::Error> {
+    /// Set how the event traces are written. By default they are written as a dense data matrix.
+    pub fn set_sparse(&mut self, sparse: &SparseConfig) {
+        self.sparse = sparse.clone();
+    }
+
+    /// Set whether the hit table of each event is written, and whether the traces are written as well
+    pub fn set_hits(&mut self, hits: &HitConfig) {
+        self.write_hits = hits.enabled;
+        self.write_traces = !hits.enabled || hits.write_traces;
+    }
+
+    /// Set the time buckets written for each trace (see Event::resample) and the GET sampling frequency, which are recorded
+    /// as attributes of the trace Datasets
+    pub fn set_sampling(&mut self, sampling: &SamplingConfig) {
+        self.sampling = sampling.clone();
+    }
+
+    /// Record the time sampling of the traces in a Dataset as attributes: the first bucket, the number of buckets averaged
+    /// into each bucket, the number of buckets, and the sampling frequency of the buckets in MHz
+    fn write_sampling_attributes(&self, dataset: &hdf5::Dataset) -> Result<(), hdf5::Error> {
+        dataset
+            .new_attr_builder()
+            .with_data(&[self.sampling.first_bucket as u32])
+            .create("first_bucket")?;
+        dataset
+            .new_attr_builder()
+            .with_data(&[self.sampling.rebin as u32])
+            .create("rebin")?;
+        dataset
+            .new_attr_builder()
+            .with_data(&[self.sampling.get_n_buckets() as u32])
+            .create("n_buckets")?;
+        dataset
+            .new_attr_builder()
+            .with_data(&[self.sampling.sampling_frequency / self.sampling.rebin as f64])
+            .create("sampling_frequency")?;
+        Ok(())
+    }
+
+    /// Write an event, where the event is converted into a data matrix (or sparse traces, see set_sparse)
+    pub fn write_event(
+        &mut self,
+        mut event: Event,
+        event_counter: &u64,
+    ) -> Result<(), hdf5::Error> {
         let header_builder = self.group.new_dataset_builder();
         let body_builder = self.group.new_dataset_builder();
         let event_body_name = format!("evt{}_data", event_counter);
         let event_header_name = format!("evt{}_header", event_counter);
+        if let Some(fpn_matrix) = event.take_fpn_data_matrix() {
+            let fpn_builder = self.group.new_dataset_builder();
+            let dataset = fpn_builder
+                .with_data(&fpn_matrix)
+                .create(format!("evt{}_fpn", event_counter).as_str())?;
+            self.write_sampling_attributes(&dataset)?;
+        }
+        if let Some(baseline_matrix) = event.take_baseline_matrix() {
+            let baseline_builder = self.group.new_dataset_builder();
+            baseline_builder
+                .with_data(&baseline_matrix)
+                .create(format!("evt{}_baseline", event_counter).as_str())?;
+        }
+        if let Some(deconvolved_matrix) = event.take_deconvolved_matrix() {
+            self.group
+                .new_dataset_builder()
+                .with_data(&deconvolved_matrix)
+                .create(format!("evt{}_deconv", event_counter).as_str())?;
+        }
+        if let Some(deconvolved_hits) = event.get_deconvolved_hit_matrix() {
+            self.group
+                .new_dataset_builder()
+                .with_data(&deconvolved_hits)
+                .create(format!("evt{}_deconv_hits", event_counter).as_str())?;
+        }
         if *event_counter == 0 {
             // Catch first event
             self.meta_data[0] = *event_counter;
@@ -69,47 +155,109 @@ impl HDFWriter {
         header_builder
             .with_data(&event.get_header_array())
             .create(event_header_name.as_str())?;
-        body_builder
-            .with_data(&event.convert_to_data_matrix())
-            .create(event_body_name.as_str())?;
+        if self.write_hits {
+            self.group
+                .new_dataset_builder()
+                .with_data(&event.get_hit_matrix())
+                .create(format!("evt{}_hits", event_counter).as_str())?;
+        }
+        if let Some(saturation_matrix) = event.get_saturation_matrix() {
+            self.group
+                .new_dataset_builder()
+                .with_data(&saturation_matrix)
+                .create(format!("evt{}_saturation", event_counter).as_str())?;
+        }
+        if let Some(point_cloud) = event.take_point_cloud() {
+            self.group
+                .new_dataset_builder()
+                .with_data(&point_cloud)
+                .create(format!("evt{}_cloud", event_counter).as_str())?;
+        }
+        if !self.write_traces {
+            return Ok(());
+        }
+        match (self.sparse.enabled, event.is_calibrated()) {
+            (true, false) => {
+                let sparse = event.convert_to_sparse(self.sparse.threshold);
+                self.write_sparse(&sparse, event_counter)?;
+            }
+            (true, true) => {
+                let sparse = event.convert_to_calibrated_sparse(self.sparse.threshold);
+                self.write_sparse(&sparse, event_counter)?;
+            }
+            (false, false) => {
+                let dataset = body_builder
+                    .with_data(&event.convert_to_data_matrix())
+                    .create(event_body_name.as_str())?;
+                self.write_sampling_attributes(&dataset)?;
+            }
+            (false, true) => {
+                let dataset = body_builder
+                    .with_data(&event.convert_to_calibrated_data_matrix())
+                    .create(event_body_name.as_str())?;
+                self.write_sampling_attributes(&dataset)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the datasets of sparse traces, see SparseTraces
+    fn write_sparse<T: hdf5::H5Type>(
+        &self,
+        sparse: &SparseTraces<T>,
+        event_counter: &u64,
+    ) -> Result<(), hdf5::Error> {
+        self.group
+            .new_dataset_builder()
+            .with_data(&sparse.pads)
+            .create(format!("evt{}_pads", event_counter).as_str())?;
+        let runs = self
+            .group
+            .new_dataset_builder()
+            .with_data(&sparse.runs)
+            .create(format!("evt{}_runs", event_counter).as_str())?;
+        self.write_sampling_attributes(&runs)?;
+        self.group
+            .new_dataset_builder()
+            .with_data(&sparse.samples)
+            .create(format!("evt{}_samples", event_counter).as_str())?;
         Ok(())
     }
 
     /// Write graw file information in meta group
     pub fn write_fileinfo(&self, merger: &Merger) -> Result<(), Box<dyn Error>> {
-        let file_stacks = merger.get_file_stacks();
-        for stack in file_stacks.iter() {
+        let frame_sources = merger.get_frame_sources();
+        for source in frame_sources.iter() {
+            let source_files = source.get_file_list();
+            if source_files.is_empty() {
+                // Not all sources read files
+                continue;
+            }
             let file_builder = self.meta.new_dataset_builder();
             let size_builder = self.meta.new_dataset_builder();
             let file_name = format!(
                 "cobo{}asad{}_files",
-                stack.get_cobo_number(),
-                stack.get_asad_number()
+                source.get_cobo_number(),
+                source.get_asad_number()
             );
             let size_name = format!(
                 "cobo{}asad{}_length",
-                stack.get_cobo_number(),
-                stack.get_asad_number()
+                source.get_cobo_number(),
+                source.get_asad_number()
             );
-            let file_stack = stack.get_file_stack_ref();
             let mut file_list = Array1::<VarLenAscii>::from_elem(
-                file_stack.len() + 1,
+                source_files.len(),
                 VarLenAscii::from_ascii("".as_bytes())?,
             );
-            let mut size_list = Array1::<u64>::zeros([file_stack.len() + 1]);
-            size_list[0] = stack.get_active_file().get_size_bytes(); // Active file is the first one
-            file_list[0] = VarLenAscii::from_ascii(
-                stack
-                    .get_active_file()
-                    .get_filename()
-                    .to_str()
-                    .unwrap()
-                    .as_bytes(),
-            )?;
-            for (row, queue) in file_stack.iter().enumerate() {
-                size_list[row + 1] = queue.metadata().unwrap().len();
-                file_list[row + 1] =
-                    VarLenAscii::from_ascii(queue.as_path().file_name().unwrap().as_bytes())?;
+            let mut size_list = Array1::<u64>::zeros([source_files.len()]);
+            for (row, (path, size)) in source_files.iter().enumerate() {
+                size_list[row] = *size;
+                // The first file is written with its full path, the rest by name
+                file_list[row] = if row == 0 {
+                    VarLenAscii::from_ascii(path.to_str().unwrap().as_bytes())?
+                } else {
+                    VarLenAscii::from_ascii(path.as_path().file_name().unwrap().as_bytes())?
+                };
             }
             size_builder
                 .with_data(&size_list)
@@ -141,6 +289,131 @@ impl HDFWriter {
         Ok(())
     }
 
+    /// Write the run-level completeness information in the meta group: how often each CoBo/AsAd was missing from an event
+    pub fn write_completeness(&self, counter: &CompletenessCounter) -> Result<(), hdf5::Error> {
+        let builder = self.meta.new_dataset_builder();
+        builder
+            .with_data(counter.get_missing_counts())
+            .create("missing_frames")?;
+        let builder = self.meta.new_dataset_builder();
+        builder
+            .with_data(&counter.get_summary_array())
+            .create("completeness")?;
+        counter.log_summary();
+        Ok(())
+    }
+
+    /// Write the stats of the event builder (frames it had to drop, etc.) in the meta group
+    pub fn write_builder_stats(&self, stats: &BuilderStats) -> Result<(), hdf5::Error> {
+        let builder = self.meta.new_dataset_builder();
+        builder
+            .with_data(&stats.get_stats_array())
+            .create("builder_stats")?;
+        stats.log_summary();
+        Ok(())
+    }
+
+    /// Write the accepted and rejected event counts of the event filter in the meta group
+    pub fn write_filter_stats(&self, filter: &EventFilter) -> Result<(), hdf5::Error> {
+        let builder = self.meta.new_dataset_builder();
+        builder
+            .with_data(&filter.get_stats_array())
+            .create("filter")?;
+        filter.log_summary();
+        Ok(())
+    }
+
+    /// Write the provenance of the pedestals subtracted from the traces in the meta group: the pedestal file, and the pedestal
+    /// run number and number of events
+    pub fn write_pedestal_info(&self, table: &PedestalTable) -> Result<(), Box<dyn Error>> {
+        let file_builder = self.meta.new_dataset_builder();
+        let file_list = Array1::<VarLenAscii>::from_elem(
+            1,
+            VarLenAscii::from_ascii(table.get_path().as_os_str().as_bytes())?,
+        );
+        file_builder.with_data(&file_list).create("pedestal_file")?;
+        let info_builder = self.meta.new_dataset_builder();
+        info_builder
+            .with_data(&[*table.get_run_number() as i64, *table.get_n_events() as i64])
+            .create("pedestal_info")?;
+        Ok(())
+    }
+
+    /// Write the provenance of the gain calibration applied to the traces in the meta group
+    pub fn write_calibration_info(
+        &self,
+        calibration: &GainCalibration,
+    ) -> Result<(), Box<dyn Error>> {
+        let file_list = Array1::<VarLenAscii>::from_elem(
+            1,
+            VarLenAscii::from_ascii(calibration.get_path().as_os_str().as_bytes())?,
+        );
+        self.meta
+            .new_dataset_builder()
+            .with_data(&file_list)
+            .create("calibration_file")?;
+        Ok(())
+    }
+
+    /// Write the channel mask used for the run in the meta group, along with the path to the mask file
+    pub fn write_channel_mask(&self, mask: &ChannelMask) -> Result<(), Box<dyn Error>> {
+        self.meta
+            .new_dataset_builder()
+            .with_data(&mask.get_mask_matrix())
+            .create("channel_mask")?;
+        let file_list = Array1::<VarLenAscii>::from_elem(
+            1,
+            VarLenAscii::from_ascii(mask.get_path().as_os_str().as_bytes())?,
+        );
+        self.meta
+            .new_dataset_builder()
+            .with_data(&file_list)
+            .create("channel_mask_file")?;
+        Ok(())
+    }
+
+    /// Write the AGET response used for deconvolution in the meta group, along with the path to the response file if it was
+    /// measured
+    pub fn write_deconvolution_info(
+        &self,
+        response: &ResponseFunction,
+    ) -> Result<(), Box<dyn Error>> {
+        self.meta
+            .new_dataset_builder()
+            .with_data(response.get_values())
+            .create("deconvolution_response")?;
+        if let Some(path) = response.get_path() {
+            let file_list = Array1::<VarLenAscii>::from_elem(
+                1,
+                VarLenAscii::from_ascii(path.as_os_str().as_bytes())?,
+            );
+            self.meta
+                .new_dataset_builder()
+                .with_data(&file_list)
+                .create("deconvolution_file")?;
+        }
+        Ok(())
+    }
+
+    /// Write the saturation stats of the run in the meta group
+    pub fn write_saturation_stats(&self, counter: &SaturationCounter) -> Result<(), hdf5::Error> {
+        self.meta
+            .new_dataset_builder()
+            .with_data(&counter.get_stats_array())
+            .create("saturation")?;
+        counter.log_summary();
+        Ok(())
+    }
+
+    /// Write the event window used to restrict the processing of the run in the meta group
+    pub fn write_window(&self, window: &EventWindow) -> Result<(), hdf5::Error> {
+        let builder = self.meta.new_dataset_builder();
+        builder
+            .with_data(&window.get_window_array())
+            .create("window")?;
+        Ok(())
+    }
+
     /// Write meta information from evt file in frib group
     pub fn write_evtinfo(&self, run_info: RunInfo) -> Result<(), hdf5::Error> {
         let builder = self.frib.new_dataset_builder();
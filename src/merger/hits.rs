@@ -0,0 +1,242 @@
+use ndarray::{Array1, Array2, ArrayView1};
+
+use super::calibration::PadGain;
+use super::config::HitConfig;
+use super::pad_map::HardwareID;
+
+/// Number of values in a row of the hit table
+pub const NUMBER_OF_HIT_COLUMNS: usize = 9;
+
+/// # Hit
+/// A peak found in a trace
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hit {
+    pub hw_id: HardwareID,
+    pub time_bucket: f64, // peak position, interpolated between buckets
+    pub amplitude: f64,
+    pub integral: f64, // sum of the (smoothed) samples of the peak
+    pub width: f64,    // full width at half maximum, in buckets
+}
+
+impl Hit {
+    /// The hit as a row of the hit table: CoBo, AsAd, AGET, Channel, Pad, time bucket, amplitude, integral, width
+    pub fn get_row(&self) -> [f64; NUMBER_OF_HIT_COLUMNS] {
+        [
+            self.hw_id.cobo_id as f64,
+            self.hw_id.asad_id as f64,
+            self.hw_id.aget_id as f64,
+            self.hw_id.channel as f64,
+            self.hw_id.pad_id as f64,
+            self.time_bucket,
+            self.amplitude,
+            self.integral,
+            self.width,
+        ]
+    }
+}
+
+/// Make the hit table of a set of hits, one row per hit (see Hit::get_row)
+pub fn hits_to_matrix(hits: &[Hit]) -> Array2<f64> {
+    let mut matrix = Array2::<f64>::zeros([hits.len(), NUMBER_OF_HIT_COLUMNS]);
+    for (row, hit) in hits.iter().enumerate() {
+        matrix
+            .row_mut(row)
+            .assign(&Array1::from_vec(hit.get_row().to_vec()));
+    }
+    matrix
+}
+
+/// Find the hits in a trace. The trace should already have its baseline removed. The samples are calibrated with the gain of
+/// the pad and smoothed with a moving average, then every region above the threshold is a hit. With multi-peak detection, a
+/// region with more than one local maximum is split at the minimum between each pair of maxima which are at least
+/// min_separation buckets apart.
+pub fn find_hits(
+    hw_id: &HardwareID,
+    trace: &ArrayView1<i16>,
+    gain: &PadGain,
+    config: &HitConfig,
+) -> Vec<Hit> {
+    find_hits_in_values(hw_id, calibrate(trace, gain), config)
+}
+
+/// Find the hits in a trace which has already been converted to values (i.e. a deconvolved trace). Same as find_hits, without
+/// the calibration.
+pub fn find_hits_in_values(
+    hw_id: &HardwareID,
+    values: Array1<f64>,
+    config: &HitConfig,
+) -> Vec<Hit> {
+    let smoothed = smooth(values, config.smoothing);
+    let mut hits: Vec<Hit> = Vec::new();
+    let mut bucket = 0;
+    while bucket < smoothed.len() {
+        if smoothed[bucket] <= config.threshold {
+            bucket += 1;
+            continue;
+        }
+        let start = bucket;
+        while bucket < smoothed.len() && smoothed[bucket] > config.threshold {
+            bucket += 1;
+        }
+        let mut edges = vec![start];
+        if config.multi_peak {
+            edges.append(&mut find_split_points(
+                &smoothed,
+                start,
+                bucket,
+                config.min_separation,
+            ));
+        }
+        edges.push(bucket);
+        for pair in edges.windows(2) {
+            hits.push(make_hit(hw_id, &smoothed, pair[0], pair[1]));
+        }
+    }
+    hits
+}
+
+/// Calibrate the non-empty samples of a trace. Empty samples are 0.
+pub fn calibrate(trace: &ArrayView1<i16>, gain: &PadGain) -> Array1<f64> {
+    trace.mapv(|sample| {
+        if sample != 0 {
+            gain.apply(sample as f64)
+        } else {
+            0.0
+        }
+    })
+}
+
+/// Take the moving average over a window of the given number of buckets. Windows of 0 or 1 do no smoothing.
+fn smooth(values: Array1<f64>, window: usize) -> Array1<f64> {
+    if window <= 1 {
+        return values;
+    }
+    let half = window / 2;
+    let mut smoothed = Array1::<f64>::zeros(values.len());
+    for idx in 0..values.len() {
+        let low = idx.saturating_sub(half);
+        let high = (idx + window - half).min(values.len());
+        smoothed[idx] = values.slice(ndarray::s![low..high]).mean().unwrap_or(0.0);
+    }
+    smoothed
+}
+
+/// The buckets at which to split the region start..end into separate peaks
+fn find_split_points(
+    values: &Array1<f64>,
+    start: usize,
+    end: usize,
+    min_separation: usize,
+) -> Vec<usize> {
+    let mut maxima: Vec<usize> = Vec::new();
+    for idx in start..end {
+        let rising = idx == start || values[idx] > values[idx - 1];
+        let falling = idx + 1 == end || values[idx] >= values[idx + 1];
+        if rising && falling {
+            match maxima.last() {
+                // Too close to the previous maximum, keep the larger
+                Some(last) if idx - last < min_separation => {
+                    if values[idx] > values[*last] {
+                        maxima.pop();
+                        maxima.push(idx);
+                    }
+                }
+                _ => maxima.push(idx),
+            }
+        }
+    }
+
+    // Split strictly after each maximum, so that on a plateau no peak is left with an empty region
+    maxima
+        .windows(2)
+        .filter_map(|pair| (pair[0] + 1..pair[1]).min_by(|a, b| values[*a].total_cmp(&values[*b])))
+        .collect()
+}
+
+/// Make a hit from the buckets start..end
+fn make_hit(hw_id: &HardwareID, values: &Array1<f64>, start: usize, end: usize) -> Hit {
+    let region = values.slice(ndarray::s![start..end]);
+    let mut peak = start;
+    for idx in start..end {
+        if values[idx] > values[peak] {
+            peak = idx;
+        }
+    }
+    let amplitude = values[peak];
+
+    // Parabolic interpolation of the peak position
+    let mut time_bucket = peak as f64;
+    if peak > 0 && peak + 1 < values.len() {
+        let denominator = values[peak - 1] - 2.0 * amplitude + values[peak + 1];
+        if denominator != 0.0 {
+            time_bucket += 0.5 * (values[peak - 1] - values[peak + 1]) / denominator;
+        }
+    }
+
+    Hit {
+        hw_id: hw_id.clone(),
+        time_bucket,
+        amplitude,
+        integral: region.sum(),
+        width: region
+            .iter()
+            .filter(|value| **value >= 0.5 * amplitude)
+            .count() as f64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(values: &[f64], multi_peak: bool) -> Vec<Hit> {
+        let config = HitConfig {
+            multi_peak,
+            ..Default::default()
+        };
+        let hw_id = HardwareID::new(&0, &0, &0, &0, &0);
+        find_hits_in_values(&hw_id, Array1::from_vec(values.to_vec()), &config)
+    }
+
+    #[test]
+    fn single_peak() {
+        let hits = find(&[0.0, 0.0, 60.0, 100.0, 60.0, 0.0, 0.0], true);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].time_bucket, 3.0);
+        assert_eq!(hits[0].amplitude, 100.0);
+        assert_eq!(hits[0].integral, 220.0);
+        assert_eq!(hits[0].width, 3.0);
+    }
+
+    #[test]
+    fn separated_peaks_are_split() {
+        let values = [
+            0.0, 60.0, 100.0, 60.0, 55.0, 55.0, 55.0, 60.0, 100.0, 60.0, 0.0,
+        ];
+        let hits = find(&values, true);
+        let peaks: Vec<f64> = hits.iter().map(|hit| hit.time_bucket).collect();
+        assert_eq!(peaks, vec![2.0, 8.0]);
+        // Split at the first bucket of the minimum between them
+        assert_eq!(hits[0].integral, 220.0);
+        assert_eq!(hits[1].integral, 385.0);
+
+        assert_eq!(find(&values, false).len(), 1);
+    }
+
+    #[test]
+    fn close_peaks_are_merged() {
+        let hits = find(&[0.0, 60.0, 100.0, 70.0, 90.0, 60.0, 0.0], true);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].amplitude, 100.0);
+    }
+
+    #[test]
+    fn plateau_has_no_empty_hits() {
+        let hits = find(&[60.0, 60.0, 60.0, 60.0, 60.0, 60.0, 100.0], true);
+        assert_eq!(hits.len(), 2);
+        assert!(hits.iter().all(|hit| hit.width >= 1.0));
+        assert_eq!(hits[0].integral, 60.0);
+        assert_eq!(hits[1].amplitude, 100.0);
+        assert_eq!(hits[1].integral, 400.0);
+    }
+}
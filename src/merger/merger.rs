@@ -1,43 +1,91 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 use std::path::PathBuf;
 
 use super::constants::{NUMBER_OF_ASADS, NUMBER_OF_COBOS};
 use super::error::AsadStackError;
 
 use super::asad_stack::AsadStack;
-use super::config::Config;
+use super::completeness::board_bit;
+use super::config::{Config, MergeMode};
 use super::error::MergerError;
+use super::frame_source::FrameSource;
 use super::graw_frame::GrawFrame;
 
+// A decrease from within this distance of the maximum to within this distance of zero is taken as a wraparound rather than a reset
+const WRAPAROUND_WINDOW: u64 = 1_000_000;
+// A decrease by more than the second value is taken as a counter reset, as is a decrease to below the first value from more than
+// RESET_ORIGIN_FACTOR times it. Smaller decreases are out of order frames, including those at the start of a run. Event ids count
+// events, timestamps count 10 ns ticks (1 s and 10 s here).
+const EVENT_ID_RESET: (u64, u64) = (10, 1000);
+const TIMESTAMP_RESET: (u64, u64) = (100_000_000, 1_000_000_000);
+const RESET_ORIGIN_FACTOR: u64 = 10;
+
+/// # FrameKey
+/// The sorting key of the next frame in a source. Frames are ordered by epoch, then by event id or timestamp (depending on the MergeMode); ties
+/// are broken by CoBo and then AsAd so that the order of frames within an event is reproducible.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct FrameKey {
+    epoch: u32,
+    order: u64,
+    cobo: i32,
+    asad: i32,
+    source_index: usize,
+}
+
 /// # Merger
 /// Merger essentially performs a merge-sort operation on the data files, taking all of the separate
 /// data from the .graw files and zipping them into a single data stream which is sorted in time.
-/// Currently uses EventID to decide the time of a frame, not the timestamp.
+/// By default uses EventID to decide the time of a frame. With MergeMode::Timestamp the frame timestamp is used instead.
+///
+/// The Merger reads from FrameSources. Normally these are the AsadStacks of a run, but any mix of sources can be merged
+/// (see Merger::from_sources).
+///
+/// The next frame of each source is kept in a min-heap, so each frame only requires refreshing the source it came from
+/// rather than querying every source.
+///
+/// ## Epochs
+/// Within a source the event id (or timestamp) should only ever increase. If it wraps around, or drops close to zero or by a large
+/// amount, the DAQ counter was reset (i.e. a restart mid-run). The source then moves to a new epoch, and all frames of an epoch are
+/// merged before any frame of the next one. The epoch is attached to each frame returned, so that the EventBuilder keeps events from
+/// different epochs apart. Small decreases are out of order frames: they stay in the same epoch, and are returned as soon as they
+/// are read so that the EventBuilder can add them to their (still open) event.
 #[derive(Debug)]
 pub struct Merger {
-    file_stacks: Vec<AsadStack>,
+    frame_sources: Vec<Box<dyn FrameSource>>,
+    source_epochs: Vec<u32>,
+    last_orders: Vec<Option<u64>>, // the ordering value of the last frame queued for each source
+    frame_queue: BinaryHeap<Reverse<FrameKey>>,
+    merge_mode: MergeMode,
     total_data_size_bytes: u64,
 }
 
 impl Merger {
-    /// Create a new merger. Requires the path to the graw data files
+    /// Create a new merger from the AsadStacks of a run. Requires the path to the graw data files
     pub fn new(config: &Config, run_number: i32) -> Result<Self, MergerError> {
-        let mut merger = Merger {
-            file_stacks: Vec::new(),
-            total_data_size_bytes: 0,
-        };
+        let mut sources: Vec<Box<dyn FrameSource>> = Vec::new();
 
-        //For every asad in every cobo, attempt to make a stack
+        //For every selected asad in every cobo, attempt to make a stack
         let mut graw_dir: PathBuf;
         for cobo in 0..NUMBER_OF_COBOS {
+            //Skip CoBos with nothing selected; their directories don't need to exist
+            if !(0..NUMBER_OF_ASADS).any(|asad| config.is_asad_selected(cobo, asad)) {
+                log::info!("CoBo {} is excluded from the merge", cobo);
+                continue;
+            }
             if config.online {
                 graw_dir = config.get_online_directory(run_number, &cobo)?;
             } else {
                 graw_dir = config.get_run_directory(run_number, &cobo)?;
             }
             for asad in 0..NUMBER_OF_ASADS {
+                if !config.is_asad_selected(cobo, asad) {
+                    log::info!("CoBo {} AsAd {} is excluded from the merge", cobo, asad);
+                    continue;
+                }
                 match AsadStack::new(&graw_dir, cobo as i32, asad as i32) {
                     Ok(stack) => {
-                        merger.file_stacks.push(stack);
+                        sources.push(Box::new(stack));
                     }
                     Err(AsadStackError::NoMatchingFiles) => {
                         continue;
@@ -49,47 +97,70 @@ impl Merger {
             }
         }
 
+        Self::from_sources(sources, config.merge_mode)
+    }
+
+    /// Create a new merger from any set of FrameSources
+    pub fn from_sources(
+        frame_sources: Vec<Box<dyn FrameSource>>,
+        merge_mode: MergeMode,
+    ) -> Result<Self, MergerError> {
         //Oops no files
-        if merger.file_stacks.len() == 0 {
+        if frame_sources.len() == 0 {
             return Err(MergerError::NoFilesError);
         }
 
-        merger.total_data_size_bytes = merger
-            .file_stacks
+        let total_data_size_bytes = frame_sources
             .iter()
-            .fold(0, |sum, stack| sum + stack.get_stack_size_bytes());
+            .fold(0, |sum, source| sum + source.get_size_bytes());
+        let mut merger = Merger {
+            frame_sources,
+            source_epochs: Vec::new(),
+            last_orders: Vec::new(),
+            frame_queue: BinaryHeap::new(),
+            merge_mode,
+            total_data_size_bytes,
+        };
+        merger.fill_queue()?;
         Ok(merger)
     }
 
-    /// Asks the stacks for the next frame. Which ever stack has the earliest event, returns its frame.
-    /// Returns Result<Option<GrawFrame>>. If the Option is None, that means that there is no more data to be read from the stacks
+    /// Asks the sources for the next frame. Which ever source has the earliest event, returns its frame.
+    /// Returns Result<Option<GrawFrame>>. If the Option is None, that means that there is no more data to be read from the sources
     pub fn get_next_frame(&mut self) -> Result<Option<GrawFrame>, MergerError> {
-        let mut earliest_event_index: Option<(usize, u32)> = Option::None;
-        for (idx, stack) in self.file_stacks.iter_mut().enumerate() {
-            if let Some(meta) = stack.get_next_frame_metadata()? {
-                match earliest_event_index {
-                    None => {
-                        earliest_event_index = Some((idx, meta.event_id));
-                    }
-                    Some((_index, event_id)) => {
-                        if meta.event_id < event_id {
-                            earliest_event_index = Some((idx, meta.event_id));
-                        }
-                    }
-                }
-            }
+        let key = match self.frame_queue.pop() {
+            Some(Reverse(key)) => key,
+            //None of the remaining sources had data for us. We've read everything.
+            None => return Ok(None),
+        };
+        let mut frame = self.frame_sources[key.source_index].get_next_frame()?;
+        frame.epoch = key.epoch;
+        //Only the source we just read from has a new next frame
+        self.push_source(key.source_index)?;
+        Ok(Some(frame))
+    }
+
+    /// Load or build the FrameIndex of every source. Stacks which already have an up-to-date sidecar file are not rescanned.
+    pub fn build_frame_indices(&mut self) -> Result<(), MergerError> {
+        for source in self.frame_sources.iter_mut() {
+            source.build_frame_index()?;
+            log::info!(
+                "Frame index ready for CoBo {} AsAd {}",
+                source.get_cobo_number(),
+                source.get_asad_number()
+            );
         }
+        Ok(())
+    }
 
-        if earliest_event_index.is_none() {
-            //None of the remaining stacks had data for us. We've read everything.
-            return Ok(None);
-        } else {
-            //This MUST happen before the retain call. The indexes will be modified.
-            let frame = self.file_stacks[earliest_event_index.unwrap().0].get_next_frame()?;
-            //Only keep stacks which still have data to be read
-            self.file_stacks.retain(|stack| stack.is_not_ended());
-            return Ok(Some(frame));
+    /// Move every source to the first frame with an event id greater than or equal to the requested one using the frame indices
+    /// (built if needed). Used to start reading partway through a run. Epochs are restarted from zero. Sources which
+    /// cannot seek are read from their current position.
+    pub fn seek_to_event(&mut self, event_id: u32) -> Result<(), MergerError> {
+        for source in self.frame_sources.iter_mut() {
+            source.seek_to_event(event_id)?;
         }
+        self.fill_queue()
     }
 
     /// Total size of the run in bytes
@@ -97,7 +168,199 @@ impl Merger {
         &self.total_data_size_bytes
     }
 
-    pub fn get_file_stacks(&self) -> &Vec<AsadStack> {
-        &self.file_stacks
+    /// The contributor mask (see completeness::board_bit) of all of the CoBo/AsAds which have data in this run
+    pub fn get_expected_boards(&self) -> u64 {
+        self.frame_sources.iter().fold(0, |mask, source| {
+            mask | board_bit(
+                source.get_cobo_number() as u8,
+                source.get_asad_number() as u8,
+            )
+        })
+    }
+
+    pub fn get_frame_sources(&self) -> &Vec<Box<dyn FrameSource>> {
+        &self.frame_sources
+    }
+
+    /// Rebuild the queue from the next frame of every source
+    fn fill_queue(&mut self) -> Result<(), MergerError> {
+        self.frame_queue.clear();
+        self.source_epochs = vec![0; self.frame_sources.len()];
+        self.last_orders = vec![None; self.frame_sources.len()];
+        for source_index in 0..self.frame_sources.len() {
+            self.push_source(source_index)?;
+        }
+        Ok(())
+    }
+
+    /// Queue the next frame of a source, if the source has any data left
+    fn push_source(&mut self, source_index: usize) -> Result<(), MergerError> {
+        let source = &mut self.frame_sources[source_index];
+        if let Some(meta) = source.get_next_frame_metadata()? {
+            let (order, max_order, (reset_origin, reset_drop)) = match self.merge_mode {
+                MergeMode::EventId => (meta.event_id as u64, u32::MAX as u64, EVENT_ID_RESET),
+                MergeMode::Timestamp => (meta.event_time, (1 << 48) - 1, TIMESTAMP_RESET), // 48-bit timestamps
+            };
+            if let Some(last_order) = self.last_orders[source_index] {
+                let kind = if order >= last_order {
+                    None
+                } else if last_order > max_order - WRAPAROUND_WINDOW && order < WRAPAROUND_WINDOW {
+                    Some("wraparound")
+                } else if last_order - order > reset_drop
+                    || (order < reset_origin && last_order > reset_origin * RESET_ORIGIN_FACTOR)
+                {
+                    Some("reset")
+                } else {
+                    log::debug!(
+                        "Out of order frame for CoBo {} AsAd {}: {} followed {}",
+                        source.get_cobo_number(),
+                        source.get_asad_number(),
+                        order,
+                        last_order
+                    );
+                    None
+                };
+                if let Some(kind) = kind {
+                    self.source_epochs[source_index] += 1;
+                    log::warn!(
+                        "Counter {} detected for CoBo {} AsAd {}: {} followed {}. Moving to epoch {}.",
+                        kind,
+                        source.get_cobo_number(),
+                        source.get_asad_number(),
+                        order,
+                        last_order,
+                        self.source_epochs[source_index]
+                    );
+                }
+            }
+            self.last_orders[source_index] = Some(order);
+            self.frame_queue.push(Reverse(FrameKey {
+                epoch: self.source_epochs[source_index],
+                order,
+                cobo: source.get_cobo_number(),
+                asad: source.get_asad_number(),
+                source_index,
+            }));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merger::frame_source::make_test_source as make_source;
+
+    /// Merge the sources, returning (epoch, event id, cobo, asad) of each frame in order
+    fn merge(sources: Vec<Box<dyn FrameSource>>) -> Vec<(u32, u32, u8, u8)> {
+        let mut merger = Merger::from_sources(sources, MergeMode::EventId).unwrap();
+        let mut merged = Vec::new();
+        while let Some(frame) = merger.get_next_frame().unwrap() {
+            merged.push((
+                frame.epoch,
+                frame.header.event_id,
+                frame.header.cobo_id,
+                frame.header.asad_id,
+            ));
+        }
+        merged
+    }
+
+    #[test]
+    fn ties_are_broken_by_cobo_then_asad() {
+        let merged = merge(vec![
+            make_source(1, 0, &[7, 8]),
+            make_source(0, 1, &[7, 8]),
+            make_source(0, 0, &[7, 8]),
+        ]);
+        assert_eq!(
+            merged,
+            vec![
+                (0, 7, 0, 0),
+                (0, 7, 0, 1),
+                (0, 7, 1, 0),
+                (0, 8, 0, 0),
+                (0, 8, 0, 1),
+                (0, 8, 1, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn counter_reset_starts_new_epoch() {
+        let merged = merge(vec![
+            make_source(0, 0, &[5000, 5001, 0, 1]),
+            make_source(1, 0, &[5000, 5001, 0, 1]),
+        ]);
+        assert_eq!(
+            merged,
+            vec![
+                (0, 5000, 0, 0),
+                (0, 5000, 1, 0),
+                (0, 5001, 0, 0),
+                (0, 5001, 1, 0),
+                (1, 0, 0, 0),
+                (1, 0, 1, 0),
+                (1, 1, 0, 0),
+                (1, 1, 1, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn large_drop_starts_new_epoch() {
+        let merged = merge(vec![make_source(0, 0, &[5000, 3000])]);
+        assert_eq!(merged, vec![(0, 5000, 0, 0), (1, 3000, 0, 0)]);
+    }
+
+    #[test]
+    fn counter_wraparound_starts_new_epoch() {
+        let merged = merge(vec![
+            make_source(0, 0, &[u32::MAX - 1, u32::MAX, 500]),
+            make_source(0, 1, &[u32::MAX, 500]),
+        ]);
+        assert_eq!(
+            merged,
+            vec![
+                (0, u32::MAX - 1, 0, 0),
+                (0, u32::MAX, 0, 0),
+                (0, u32::MAX, 0, 1),
+                (1, 500, 0, 0),
+                (1, 500, 0, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn drop_to_origin_starts_new_epoch() {
+        let merged = merge(vec![make_source(0, 0, &[500, 2])]);
+        assert_eq!(merged, vec![(0, 500, 0, 0), (1, 2, 0, 0)]);
+    }
+
+    #[test]
+    fn out_of_order_frame_at_start_stays_in_epoch() {
+        let merged = merge(vec![
+            make_source(0, 0, &[3, 5, 4, 6]),
+            make_source(0, 1, &[3, 4, 5, 6]),
+        ]);
+        assert!(merged.iter().all(|(epoch, _, _, _)| *epoch == 0));
+        assert_eq!(merged.last(), Some(&(0, 6, 0, 1)));
+    }
+
+    #[test]
+    fn out_of_order_frame_stays_in_epoch() {
+        let merged = merge(vec![
+            make_source(0, 0, &[100, 102, 101, 103]),
+            make_source(0, 1, &[100, 101, 102, 103]),
+        ]);
+        assert!(merged.iter().all(|(epoch, _, _, _)| *epoch == 0));
+        assert_eq!(
+            merged
+                .iter()
+                .filter(|(_, _, _, asad)| *asad == 0)
+                .map(|(_, event_id, _, _)| *event_id)
+                .collect::<Vec<u32>>(),
+            vec![100, 102, 101, 103]
+        );
     }
 }
@@ -1,15 +1,30 @@
 pub mod asad_stack;
+pub mod baseline;
+pub mod calibration;
+pub mod channel_mask;
+pub mod completeness;
 pub mod config;
 pub mod constants;
+pub mod deconvolution;
 pub mod error;
 pub mod event;
 pub mod event_builder;
+pub mod event_filter;
 pub mod evt_file;
 pub mod evt_stack;
+pub mod extract;
+pub mod frame_index;
+pub mod frame_source;
 pub mod graw_file;
 pub mod graw_frame;
 pub mod hdf_writer;
+pub mod hits;
 pub mod merger;
+pub mod pad_geometry;
 pub mod pad_map;
+pub mod pedestal;
 pub mod process;
 pub mod ring_item;
+pub mod saturation;
+pub mod sparse;
+pub mod trace_processor;
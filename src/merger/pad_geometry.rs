@@ -0,0 +1,58 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use fxhash::FxHashMap;
+
+use super::error::PadGeometryError;
+
+const ENTRIES_PER_LINE: usize = 4; //Number of elements in a single row in the CSV file
+
+/// # PadPosition
+/// The position of the center of a pad on the pad plane and its area
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PadPosition {
+    pub x: f64,
+    pub y: f64,
+    pub area: f64,
+}
+
+/// # PadGeometry
+/// PadGeometry maps AT-TPC pad numbers to their position on the pad plane. Like the PadMap, it is read from a CSV file, where each
+/// row contains 4 elements: the pad number, x, y, and area. Positions and areas are in the units of the file (usually mm and mm^2).
+#[derive(Debug, Clone, Default)]
+pub struct PadGeometry {
+    map: FxHashMap<usize, PadPosition>,
+}
+
+impl PadGeometry {
+    /// Create a new PadGeometry using the CSV file at the given path
+    pub fn new(path: &Path) -> Result<Self, PadGeometryError> {
+        let mut file = File::open(path)?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+
+        let mut geometry = PadGeometry::default();
+        for line in contents.lines() {
+            let entries: Vec<&str> = line.split_terminator(',').collect();
+            if entries.len() < ENTRIES_PER_LINE {
+                return Err(PadGeometryError::BadFileFormat);
+            }
+            let pad_id: usize = entries[0].parse()?;
+            geometry.map.insert(
+                pad_id,
+                PadPosition {
+                    x: entries[1].parse()?,
+                    y: entries[2].parse()?,
+                    area: entries[3].parse()?,
+                },
+            );
+        }
+        Ok(geometry)
+    }
+
+    /// Get the position of a pad. If returns None the pad is not in the geometry
+    pub fn get_position(&self, pad_id: &usize) -> Option<&PadPosition> {
+        self.map.get(pad_id)
+    }
+}
@@ -5,6 +5,7 @@ use std::path::Path;
 
 use fxhash::FxHashMap;
 
+use super::config::TraceOrder;
 use super::error::PadMapError;
 
 const ENTRIES_PER_LINE: usize = 5; //Number of elements in a single row in the CSV file
@@ -30,6 +31,26 @@ impl HardwareID {
             pad_id: *pad_id as usize,
         }
     }
+
+    /// The key used to sort HardwareIDs in the given order
+    pub fn sort_key(&self, order: TraceOrder) -> [usize; 5] {
+        match order {
+            TraceOrder::HardwareAddress => [
+                self.cobo_id,
+                self.asad_id,
+                self.aget_id,
+                self.channel,
+                self.pad_id,
+            ],
+            TraceOrder::Pad => [
+                self.pad_id,
+                self.cobo_id,
+                self.asad_id,
+                self.aget_id,
+                self.channel,
+            ],
+        }
+    }
 }
 
 impl Hash for HardwareID {
@@ -106,4 +127,34 @@ impl PadMap {
         let val = self.map.get(&uuid);
         return val;
     }
+
+    /// Remove a channel from the map, so that its data is skipped. Returns the removed HardwareID, if the channel was in the map
+    pub fn remove_hardware_id(
+        &mut self,
+        cobo_id: &u8,
+        asad_id: &u8,
+        aget_id: &u8,
+        channel_id: &u8,
+    ) -> Option<HardwareID> {
+        let uuid = generate_uuid(cobo_id, asad_id, aget_id, channel_id);
+        self.map.remove(&uuid)
+    }
+
+    /// Iterate over every HardwareID in the map
+    pub fn iter(&self) -> impl Iterator<Item = &HardwareID> {
+        self.map.values()
+    }
+
+    /// Make a map from rows of CoBo, AsAd, AGET, Channel, Pad, as in the CSV file
+    #[cfg(test)]
+    pub fn from_rows(rows: &[[u8; 5]]) -> Self {
+        let mut pm = PadMap::default();
+        for [cb_id, ad_id, ag_id, ch_id, pd_id] in rows.iter() {
+            pm.map.insert(
+                generate_uuid(cb_id, ad_id, ag_id, ch_id),
+                HardwareID::new(cb_id, ad_id, ag_id, ch_id, &(*pd_id as u64)),
+            );
+        }
+        pm
+    }
 }
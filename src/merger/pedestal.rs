@@ -0,0 +1,222 @@
+use std::path::{Path, PathBuf};
+
+use fxhash::FxHashMap;
+use ndarray::{Array1, Array2};
+
+use super::channel_mask::load_masked_pad_map;
+use super::config::Config;
+use super::constants::NUMBER_OF_TIME_BUCKETS;
+use super::error::{PedestalError, ProcessorError};
+use super::event::Event;
+use super::event_builder::EventBuilder;
+use super::merger::Merger;
+
+/// The hardware address of a channel: (CoBo, AsAd, AGET, Channel)
+pub type ChannelAddress = (u8, u8, u8, u8);
+
+/// # PedestalEntry
+/// The pedestal of a single channel: the mean and RMS of each time bucket
+#[derive(Debug, Clone)]
+pub struct PedestalEntry {
+    pub mean: Array1<f32>,
+    pub rms: Array1<f32>,
+}
+
+/// # PedestalTable
+/// A table of the pedestal of every channel read in a pedestal run. Made by analyze_pedestal_run and stored in an HDF5 file,
+/// which can then be used to subtract the pedestals from the traces of physics runs.
+///
+/// ## Format
+/// The pedestal file contains the Datasets "addresses" (N x 4, CoBo, AsAd, AGET, Channel), "mean" and "rms" (N x 512, one
+/// row per address), and "info" (the pedestal run number and the number of events used).
+#[derive(Debug, Clone, Default)]
+pub struct PedestalTable {
+    path: PathBuf,
+    run_number: i32,
+    n_events: u64,
+    entries: FxHashMap<ChannelAddress, PedestalEntry>,
+}
+
+impl PedestalTable {
+    /// Read a table from a pedestal file
+    pub fn read(path: &Path) -> Result<Self, PedestalError> {
+        let file = hdf5::File::open(path)?;
+        let addresses = file.dataset("addresses")?.read_2d::<i16>()?;
+        let means = file.dataset("mean")?.read_2d::<f32>()?;
+        let rmss = file.dataset("rms")?.read_2d::<f32>()?;
+        let info = file.dataset("info")?.read_1d::<i64>()?;
+        let n_rows = addresses.nrows();
+        if addresses.ncols() != 4
+            || means.dim() != (n_rows, NUMBER_OF_TIME_BUCKETS as usize)
+            || rmss.dim() != means.dim()
+            || info.len() != 2
+        {
+            return Err(PedestalError::BadFileFormat(path.to_path_buf()));
+        }
+
+        let mut table = PedestalTable {
+            path: path.to_path_buf(),
+            run_number: info[0] as i32,
+            n_events: info[1] as u64,
+            entries: FxHashMap::default(),
+        };
+        for row in 0..n_rows {
+            table.entries.insert(
+                (
+                    addresses[[row, 0]] as u8,
+                    addresses[[row, 1]] as u8,
+                    addresses[[row, 2]] as u8,
+                    addresses[[row, 3]] as u8,
+                ),
+                PedestalEntry {
+                    mean: means.row(row).to_owned(),
+                    rms: rmss.row(row).to_owned(),
+                },
+            );
+        }
+        Ok(table)
+    }
+
+    /// Write the table to a pedestal file
+    pub fn write(&self, path: &Path) -> Result<(), PedestalError> {
+        let n_rows = self.entries.len();
+        let mut addresses = Array2::<i16>::zeros([n_rows, 4]);
+        let mut means = Array2::<f32>::zeros([n_rows, NUMBER_OF_TIME_BUCKETS as usize]);
+        let mut rmss = Array2::<f32>::zeros([n_rows, NUMBER_OF_TIME_BUCKETS as usize]);
+        // Sort the rows so that the file is easy to read by eye
+        let mut sorted: Vec<(&ChannelAddress, &PedestalEntry)> = self.entries.iter().collect();
+        sorted.sort_by_key(|(address, _)| **address);
+        for (row, (address, entry)) in sorted.into_iter().enumerate() {
+            addresses[[row, 0]] = address.0 as i16;
+            addresses[[row, 1]] = address.1 as i16;
+            addresses[[row, 2]] = address.2 as i16;
+            addresses[[row, 3]] = address.3 as i16;
+            means.row_mut(row).assign(&entry.mean);
+            rmss.row_mut(row).assign(&entry.rms);
+        }
+
+        let file = hdf5::File::create(path)?;
+        file.new_dataset_builder()
+            .with_data(&addresses)
+            .create("addresses")?;
+        file.new_dataset_builder()
+            .with_data(&means)
+            .create("mean")?;
+        file.new_dataset_builder().with_data(&rmss).create("rms")?;
+        file.new_dataset_builder()
+            .with_data(&[self.run_number as i64, self.n_events as i64])
+            .create("info")?;
+        Ok(())
+    }
+
+    /// The pedestal of a channel, if it was read in the pedestal run
+    pub fn get_entry(&self, address: &ChannelAddress) -> Option<&PedestalEntry> {
+        self.entries.get(address)
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn get_run_number(&self) -> &i32 {
+        &self.run_number
+    }
+
+    pub fn get_n_events(&self) -> &u64 {
+        &self.n_events
+    }
+}
+
+/// # PedestalAccumulator
+/// Accumulates the samples of every channel over the events of a pedestal run. Empty buckets are not counted.
+#[derive(Debug, Default)]
+struct PedestalAccumulator {
+    n_events: u64,
+    sums: FxHashMap<ChannelAddress, (Array1<f64>, Array1<f64>, Array1<f64>)>, // sum, sum of squares, count
+}
+
+impl PedestalAccumulator {
+    fn add_event(&mut self, event: &Event) {
+        for (hw_id, trace) in event.iter_traces() {
+            let address = (
+                hw_id.cobo_id as u8,
+                hw_id.asad_id as u8,
+                hw_id.aget_id as u8,
+                hw_id.channel as u8,
+            );
+            let (sum, sum_sq, count) = self.sums.entry(address).or_insert_with(|| {
+                (
+                    Array1::<f64>::zeros(NUMBER_OF_TIME_BUCKETS as usize),
+                    Array1::<f64>::zeros(NUMBER_OF_TIME_BUCKETS as usize),
+                    Array1::<f64>::zeros(NUMBER_OF_TIME_BUCKETS as usize),
+                )
+            });
+            for (idx, sample) in trace.iter().enumerate() {
+                if *sample != 0 {
+                    let value = *sample as f64;
+                    sum[idx] += value;
+                    sum_sq[idx] += value * value;
+                    count[idx] += 1.0;
+                }
+            }
+        }
+        self.n_events += 1;
+    }
+
+    fn into_table(self, run_number: i32) -> PedestalTable {
+        let mut table = PedestalTable {
+            path: PathBuf::new(),
+            run_number,
+            n_events: self.n_events,
+            entries: FxHashMap::default(),
+        };
+        for (address, (sum, sum_sq, count)) in self.sums.into_iter() {
+            let mut mean = Array1::<f32>::zeros(NUMBER_OF_TIME_BUCKETS as usize);
+            let mut rms = Array1::<f32>::zeros(NUMBER_OF_TIME_BUCKETS as usize);
+            for idx in 0..(NUMBER_OF_TIME_BUCKETS as usize) {
+                if count[idx] > 0.0 {
+                    let bucket_mean = sum[idx] / count[idx];
+                    let variance = (sum_sq[idx] / count[idx] - bucket_mean * bucket_mean).max(0.0);
+                    mean[idx] = bucket_mean as f32;
+                    rms[idx] = variance.sqrt() as f32;
+                }
+            }
+            table.entries.insert(address, PedestalEntry { mean, rms });
+        }
+        table
+    }
+}
+
+/// Analyze a pedestal run: merge the raw traces of every event and find the mean and RMS of each time bucket of each channel.
+/// No trace processing is applied. The table is written to the output path. Returns the number of events used.
+pub fn analyze_pedestal_run(
+    config: &Config,
+    run_number: i32,
+    output_path: &Path,
+) -> Result<u64, ProcessorError> {
+    let (pad_map, _) = load_masked_pad_map(config)?;
+    let mut merger = Merger::new(config, run_number)?;
+    let mut evb = EventBuilder::new(pad_map, config);
+    let mut accumulator = PedestalAccumulator::default();
+
+    log::info!("Analyzing pedestal run {}...", run_number);
+    while let Some(frame) = merger.get_next_frame()? {
+        if let Some(event) = evb.append_frame(frame)? {
+            accumulator.add_event(&event);
+        }
+    }
+    for event in evb.flush_events() {
+        accumulator.add_event(&event);
+    }
+    evb.get_stats().log_summary();
+
+    let table = accumulator.into_table(run_number);
+    table.write(output_path)?;
+    log::info!(
+        "Wrote pedestals of {} channels from {} events to {}",
+        table.entries.len(),
+        table.n_events,
+        output_path.display()
+    );
+    Ok(table.n_events)
+}
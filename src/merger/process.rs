@@ -5,30 +5,51 @@ use crate::merger::ring_item::{
     BeginRunItem, CounterItem, EndRunItem, PhysicsItem, RingType, RunInfo, ScalersItem,
 };
 
-use super::config::Config;
+use super::channel_mask::load_masked_pad_map;
+use super::completeness::CompletenessCounter;
+use super::config::{Config, EventWindow};
 use super::constants::SIZE_UNIT;
 use super::error::ProcessorError;
+use super::event::Event;
 use super::event_builder::EventBuilder;
+use super::event_filter::EventFilter;
 use super::evt_stack::EvtStack;
 use super::hdf_writer::HDFWriter;
 use super::merger::Merger;
-use super::pad_map::PadMap;
 
-/// The final event of the EventBuilder will need a manual flush
-fn flush_final_event(
-    mut evb: EventBuilder,
-    mut writer: HDFWriter,
-    event_counter: &u64,
-) -> Result<(), hdf5::Error> {
-    if let Some(event) = evb.flush_final_event() {
-        writer.write_event(event, &event_counter)
-    } else {
-        Ok(())
+use super::trace_processor::TraceProcessor;
+
+/// Process and write an event if it is inside the event window and passes the filter. The filter sees the raw event, so rejected
+/// events are not processed. Returns false if the window has been passed, and reading should stop.
+fn handle_event(
+    mut event: Event,
+    window: &EventWindow,
+    processor: &mut TraceProcessor,
+    filter: &mut EventFilter,
+    writer: &mut HDFWriter,
+    completeness: &mut CompletenessCounter,
+    event_counter: &mut u64,
+) -> Result<bool, hdf5::Error> {
+    if window.is_passed(&event, event_counter) {
+        return Ok(false);
+    }
+    if window.contains(&event) {
+        completeness.record(&event);
+        if filter.accept(&event) {
+            processor.process(&mut event);
+            writer.write_event(event, event_counter)?;
+            *event_counter += 1;
+        }
     }
+    Ok(true)
 }
 
-/// Process the evt data for this run
-fn process_evt_data(evt_path: PathBuf, writer: &HDFWriter) -> Result<(), ProcessorError> {
+/// Process the evt data for this run. The coincidence register of each physics event is given to the filter.
+fn process_evt_data(
+    evt_path: PathBuf,
+    writer: &HDFWriter,
+    filter: &mut EventFilter,
+) -> Result<(), ProcessorError> {
     let mut evt_stack = EvtStack::new(&evt_path)?; // open evt file
     let mut run_info = RunInfo::new();
     let mut scaler_counter: u32 = 0;
@@ -58,7 +79,9 @@ fn process_evt_data(evt_path: PathBuf, writer: &HDFWriter) -> Result<(), Process
                 RingType::Physics => {
                     // Physics data
                     ring.remove_boundaries(); // physics event often cross VMUSB buffer boundary
-                    writer.write_physics(PhysicsItem::try_from(ring)?, &event_counter.count)?;
+                    let physics = PhysicsItem::try_from(ring)?;
+                    filter.record_coincidence(event_counter.count, physics.coinc.coinc);
+                    writer.write_physics(physics, &event_counter.count)?;
                     event_counter.count += 1;
                 }
                 RingType::Counter => {
@@ -79,59 +102,144 @@ pub fn process_run(
     config: &Config,
     run_number: i32,
     progress: Arc<Mutex<f32>>,
+) -> Result<(), ProcessorError> {
+    let merger = Merger::new(config, run_number)?;
+    process_merged_run(config, run_number, merger, progress)
+}
+
+/// Process a run using an existing Merger, which can be merging any mix of FrameSources. The evt data, pad map, and output
+/// file are still taken from the config.
+pub fn process_merged_run(
+    config: &Config,
+    run_number: i32,
+    mut merger: Merger,
+    progress: Arc<Mutex<f32>>,
 ) -> Result<(), ProcessorError> {
     let evt_path = config.get_evt_directory(run_number)?;
     let hdf_path = config.get_hdf_file_name(run_number)?;
-    let pad_map = PadMap::new(&config.pad_map_path)?;
+    let (pad_map, mask) = load_masked_pad_map(config)?;
 
-    //Initialize the merger, event builder, and hdf writer
-    let mut merger = Merger::new(config, run_number)?;
+    //Initialize the event builder, and hdf writer
     log::info!(
         "Total run size: {}",
         human_bytes::human_bytes(*merger.get_total_data_size() as f64)
     );
-    let mut evb = EventBuilder::new(pad_map);
+    let mut evb = EventBuilder::new(pad_map, config);
     let mut writer = HDFWriter::new(&hdf_path)?;
+    writer.set_sparse(&config.sparse);
+    writer.set_hits(&config.hits);
+    writer.set_sampling(&config.sampling);
+    let mut processor = TraceProcessor::new(config, run_number)?;
+    let mut filter = EventFilter::new(&config.event_filter);
 
     let total_data_size = merger.get_total_data_size();
     let flush_frac: f32 = 0.01;
     let mut count = 0;
     let flush_val = (*total_data_size as f64 * flush_frac as f64) as u64;
+    if flush_val == 0 {
+        log::info!(
+            "The size of the run is unknown, progress will only be shown once the run is done."
+        );
+    }
 
     //Handle the evt data
     log::info!("Now processing evt data...");
-    process_evt_data(evt_path, &writer)?;
+    process_evt_data(evt_path, &writer, &mut filter)?;
     log::info!("Done with evt data.");
 
     //Handle the get data
     log::info!("Processing get data...");
     writer.write_fileinfo(&merger).unwrap();
-    let mut event_counter = 0;
+    let mut completeness = CompletenessCounter::new(merger.get_expected_boards());
+    let window = &config.event_window;
+    if let Some(first_event_id) = window.first_event_id {
+        // Online the files are still growing, so an index would be stale immediately. Read from the start instead.
+        if config.online {
+            log::info!(
+                "Online mode, reading from the start of the run up to event {}...",
+                first_event_id
+            );
+        } else {
+            log::info!("Seeking to event {}...", first_event_id);
+            merger.seek_to_event(first_event_id)?;
+        }
+    }
+    let mut event_counter: u64 = 0;
     loop {
         if let Some(frame) = merger.get_next_frame()? {
             //Merger found a frame
             //bleh
             count += (frame.header.frame_size as u32 * SIZE_UNIT) as u64;
-            if count > flush_val {
+            if flush_val != 0 && count > flush_val {
                 count = 0;
                 if let Ok(mut bar) = progress.lock() {
-                    *bar += flush_frac;
+                    *bar = (*bar + flush_frac).min(1.0);
                 }
             }
 
             if let Some(event) = evb.append_frame(frame)? {
-                writer.write_event(event, &event_counter)?;
-                event_counter += 1;
+                if !handle_event(
+                    event,
+                    window,
+                    &mut processor,
+                    &mut filter,
+                    &mut writer,
+                    &mut completeness,
+                    &mut event_counter,
+                )? {
+                    log::info!("Passed the end of the event window, stopping early.");
+                    break;
+                }
             } else {
                 continue;
             }
         } else {
             //If the merger returns none, there is no more data to be read
-            writer.write_meta()?; // write meta dataset (first and last event id + ts)
-            flush_final_event(evb, writer, &event_counter)?;
+            //The open events of the EventBuilder will need a manual flush
+            for event in evb.flush_events() {
+                if !handle_event(
+                    event,
+                    window,
+                    &mut processor,
+                    &mut filter,
+                    &mut writer,
+                    &mut completeness,
+                    &mut event_counter,
+                )? {
+                    break;
+                }
+            }
             break;
         }
     }
+    writer.write_meta()?; // write meta dataset (first and last event id + ts)
+    writer.write_completeness(&completeness)?;
+    writer.write_builder_stats(evb.get_stats())?;
+    writer.write_filter_stats(&filter)?;
+    if let Some(counter) = processor.get_saturation_counter() {
+        writer.write_saturation_stats(counter)?;
+    }
+    if let Some(calibration) = processor.get_calibration() {
+        if let Err(e) = writer.write_calibration_info(calibration) {
+            log::error!("Could not write the calibration file information: {}", e);
+        }
+    }
+    if let Some(response) = processor.get_response() {
+        if let Err(e) = writer.write_deconvolution_info(response) {
+            log::error!("Could not write the deconvolution response: {}", e);
+        }
+    }
+    if let Some(mask) = &mask {
+        if let Err(e) = writer.write_channel_mask(mask) {
+            log::error!("Could not write the channel mask: {}", e);
+        }
+    }
+    if let Some(table) = processor.get_pedestal_table() {
+        if let Err(e) = writer.write_pedestal_info(table) {
+            log::error!("Could not write the pedestal file information: {}", e);
+        }
+    }
+    writer.write_window(window)?;
     if let Ok(mut bar) = progress.lock() {
         *bar = 1.0;
     }
@@ -155,3 +263,25 @@ pub fn process(config: Config, progress: Arc<Mutex<f32>>) -> Result<(), Processo
     }
     Ok(())
 }
+
+/// Build the frame index sidecar files for every run in the config. Runs are not merged.
+pub fn index(config: Config, progress: Arc<Mutex<f32>>) -> Result<(), ProcessorError> {
+    let n_runs = (config.last_run_number - config.first_run_number + 1).max(1) as f32;
+    if let Ok(mut bar) = progress.lock() {
+        *bar = 0.0;
+    }
+    for run in config.first_run_number..(config.last_run_number + 1) {
+        if config.does_run_exist(run) {
+            log::info!("Indexing run {}...", run);
+            let mut merger = Merger::new(&config, run)?;
+            merger.build_frame_indices()?;
+            log::info!("Finished indexing run {}.", run);
+        } else {
+            log::info!("Run {} does not exist, skipping...", run);
+        }
+        if let Ok(mut bar) = progress.lock() {
+            *bar += 1.0 / n_runs;
+        }
+    }
+    Ok(())
+}
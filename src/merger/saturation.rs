@@ -0,0 +1,100 @@
+use ndarray::{Array2, ArrayView1};
+
+use super::config::SaturationConfig;
+use super::pad_map::HardwareID;
+
+/// # Saturation
+/// The number of buckets of a trace which hit the ceiling or the floor of the ADC
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Saturation {
+    pub ceiling: u32,
+    pub floor: u32,
+}
+
+impl Saturation {
+    /// Count the saturated buckets of a raw trace
+    pub fn new(trace: &ArrayView1<i16>, config: &SaturationConfig) -> Self {
+        let mut saturation = Saturation::default();
+        for sample in trace.iter() {
+            if *sample >= config.ceiling {
+                saturation.ceiling += 1;
+            } else if *sample != 0 && *sample <= config.floor {
+                saturation.floor += 1;
+            }
+        }
+        saturation
+    }
+
+    pub fn is_saturated(&self) -> bool {
+        self.ceiling != 0 || self.floor != 0
+    }
+}
+
+/// Make the saturation table of an event, one row per saturated trace: CoBo, AsAd, AGET, Channel, Pad, ceiling buckets,
+/// floor buckets
+pub fn saturation_to_matrix<'a>(
+    saturated: impl ExactSizeIterator<Item = (&'a HardwareID, &'a Saturation)>,
+) -> Array2<u32> {
+    let mut matrix = Array2::<u32>::zeros([saturated.len(), 7]);
+    for (row, (hw_id, saturation)) in saturated.enumerate() {
+        matrix[[row, 0]] = hw_id.cobo_id as u32;
+        matrix[[row, 1]] = hw_id.asad_id as u32;
+        matrix[[row, 2]] = hw_id.aget_id as u32;
+        matrix[[row, 3]] = hw_id.channel as u32;
+        matrix[[row, 4]] = hw_id.pad_id as u32;
+        matrix[[row, 5]] = saturation.ceiling;
+        matrix[[row, 6]] = saturation.floor;
+    }
+    matrix
+}
+
+/// # SaturationCounter
+/// Tracks the saturation over a run
+#[derive(Debug, Clone, Default)]
+pub struct SaturationCounter {
+    events: u64,
+    saturated_events: u64,
+    saturated_traces: u64,
+    ceiling_buckets: u64,
+    floor_buckets: u64,
+}
+
+impl SaturationCounter {
+    /// Record the saturated traces of an event
+    pub fn record<'a>(&mut self, saturated: impl Iterator<Item = &'a Saturation>) {
+        let mut any_saturated = false;
+        for saturation in saturated {
+            any_saturated = true;
+            self.saturated_traces += 1;
+            self.ceiling_buckets += saturation.ceiling as u64;
+            self.floor_buckets += saturation.floor as u64;
+        }
+        self.events += 1;
+        if any_saturated {
+            self.saturated_events += 1;
+        }
+    }
+
+    /// Log a summary of the saturation
+    pub fn log_summary(&self) {
+        log::info!(
+            "{} of {} events had saturated traces ({} traces, {} buckets at the ceiling, {} buckets at the floor)",
+            self.saturated_events,
+            self.events,
+            self.saturated_traces,
+            self.ceiling_buckets,
+            self.floor_buckets
+        );
+    }
+
+    /// The stats as an array of [events, saturated events, saturated traces, ceiling buckets, floor buckets]
+    pub fn get_stats_array(&self) -> [u64; 5] {
+        [
+            self.events,
+            self.saturated_events,
+            self.saturated_traces,
+            self.ceiling_buckets,
+            self.floor_buckets,
+        ]
+    }
+}
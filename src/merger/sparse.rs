@@ -0,0 +1,213 @@
+use ndarray::{Array1, Array2, ArrayView1};
+
+use super::pad_map::HardwareID;
+
+/// # SparseTraces
+/// The traces of an event stored as runs of consecutive time buckets, rather than as 512 samples padded with zeros.
+///
+/// ## Layout
+/// - pads: one row per trace, CoBo, AsAd, AGET, Channel, Pad
+/// - runs: one row per run, the row of the trace in pads, the first time bucket, the number of buckets, and the offset of the
+/// first sample in samples
+/// - samples: the samples of every run, one after the other
+///
+/// The samples are i16, or f32 if the event is calibrated.
+#[derive(Debug, Clone)]
+pub struct SparseTraces<T> {
+    pub pads: Array2<i16>,
+    pub runs: Array2<u32>,
+    pub samples: Array1<T>,
+}
+
+impl<T> SparseTraces<T> {
+    /// Compress a set of traces with their read masks. Buckets are kept if they were read, and if a threshold is given, only if
+    /// the sample is above the threshold. Traces with no buckets kept are dropped. The kept samples are converted with the given
+    /// function (i.e. to calibrate them).
+    pub fn new<'a>(
+        traces: impl Iterator<Item = (&'a HardwareID, ArrayView1<'a, i16>, &'a [bool])>,
+        threshold: Option<i16>,
+        convert: impl Fn(&HardwareID, i16) -> T,
+    ) -> Self {
+        let mut pads: Vec<[i16; 5]> = Vec::new();
+        let mut runs: Vec<[u32; 4]> = Vec::new();
+        let mut samples: Vec<T> = Vec::new();
+        for (hw_id, trace, read) in traces {
+            let trace_row = pads.len() as u32;
+            let mut run_start: Option<usize> = None;
+            for (bucket, (sample, read)) in trace.iter().zip(read.iter()).enumerate() {
+                let keep = *read && threshold.is_none_or(|min| *sample > min);
+                match (keep, run_start) {
+                    (true, None) => run_start = Some(bucket),
+                    (false, Some(start)) => {
+                        runs.push(make_run(trace_row, start, bucket, samples.len()));
+                        samples.extend(
+                            trace
+                                .slice(ndarray::s![start..bucket])
+                                .iter()
+                                .map(|sample| convert(hw_id, *sample)),
+                        );
+                        run_start = None;
+                    }
+                    _ => (),
+                }
+            }
+            if let Some(start) = run_start {
+                runs.push(make_run(trace_row, start, trace.len(), samples.len()));
+                samples.extend(
+                    trace
+                        .slice(ndarray::s![start..])
+                        .iter()
+                        .map(|sample| convert(hw_id, *sample)),
+                );
+            }
+            if runs.last().is_some_and(|run| run[0] == trace_row) {
+                pads.push([
+                    hw_id.cobo_id as i16,
+                    hw_id.asad_id as i16,
+                    hw_id.aget_id as i16,
+                    hw_id.channel as i16,
+                    hw_id.pad_id as i16,
+                ]);
+            }
+        }
+
+        SparseTraces {
+            pads: to_matrix(pads),
+            runs: to_matrix(runs),
+            samples: Array1::from_vec(samples),
+        }
+    }
+}
+
+/// A run of the buckets start..end of a trace
+fn make_run(trace_row: u32, start: usize, end: usize, offset: usize) -> [u32; 4] {
+    [trace_row, start as u32, (end - start) as u32, offset as u32]
+}
+
+/// Stack fixed size rows into a matrix
+fn to_matrix<T, const N: usize>(rows: Vec<[T; N]>) -> Array2<T> {
+    let n_rows = rows.len();
+    Array2::from_shape_vec((n_rows, N), rows.into_iter().flatten().collect())
+        .expect("Rows of fixed length always fit the matrix")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_id(channel: u8) -> HardwareID {
+        HardwareID::new(&1, &2, &3, &channel, &(channel as u64 + 100))
+    }
+
+    #[test]
+    fn runs_split_on_gaps() {
+        let hw_id = make_id(4);
+        let trace = Array1::from_vec(vec![0, 5, 6, 0, 0, 7, 0, 8]);
+        let read = [false, true, true, false, false, true, false, true];
+        let sparse = SparseTraces::new(
+            std::iter::once((&hw_id, trace.view(), &read[..])),
+            None,
+            |_, sample| sample,
+        );
+        assert_eq!(sparse.pads.row(0).to_vec(), vec![1, 2, 3, 4, 104]);
+        assert_eq!(
+            sparse.runs,
+            ndarray::arr2(&[[0, 1, 2, 0], [0, 5, 1, 2], [0, 7, 1, 3]])
+        );
+        assert_eq!(sparse.samples.to_vec(), vec![5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn read_zero_samples_are_kept() {
+        let hw_id = make_id(0);
+        // A read sample of 0 (e.g. after pedestal subtraction) is data, not an empty bucket
+        let trace = Array1::from_vec(vec![3, 0, -2]);
+        let read = [true; 3];
+        let sparse = SparseTraces::new(
+            std::iter::once((&hw_id, trace.view(), &read[..])),
+            None,
+            |_, sample| sample,
+        );
+        assert_eq!(sparse.runs, ndarray::arr2(&[[0, 0, 3, 0]]));
+        assert_eq!(sparse.samples.to_vec(), vec![3, 0, -2]);
+    }
+
+    #[test]
+    fn threshold_cut() {
+        let (first, second) = (make_id(0), make_id(1));
+        let trace = Array1::from_vec(vec![10, 50, 60, 10, 70]);
+        let quiet = Array1::from_vec(vec![10, 20, 30, 20, 10]);
+        let read = [true; 5];
+        let sparse = SparseTraces::new(
+            [
+                (&first, trace.view(), &read[..]),
+                (&second, quiet.view(), &read[..]),
+            ]
+            .into_iter(),
+            Some(40),
+            |_, sample| sample,
+        );
+        // The trace with nothing above the threshold is dropped
+        assert_eq!(sparse.pads.nrows(), 1);
+        assert_eq!(sparse.runs, ndarray::arr2(&[[0, 1, 2, 0], [0, 4, 1, 2]]));
+        assert_eq!(sparse.samples.to_vec(), vec![50, 60, 70]);
+    }
+
+    #[test]
+    fn calibrated_samples_are_converted() {
+        let (first, second) = (make_id(0), make_id(1));
+        let trace = Array1::from_vec(vec![0, 10, 20]);
+        let read = [false, true, true];
+        let sparse = SparseTraces::new(
+            [
+                (&first, trace.view(), &read[..]),
+                (&second, trace.view(), &read[..]),
+            ]
+            .into_iter(),
+            None,
+            |hw_id, sample| sample as f32 * (hw_id.channel as f32 + 1.0),
+        );
+        assert_eq!(sparse.runs, ndarray::arr2(&[[0, 1, 2, 0], [1, 1, 2, 2]]));
+        assert_eq!(sparse.samples.to_vec(), vec![10.0, 20.0, 20.0, 40.0]);
+    }
+
+    #[test]
+    fn dense_round_trip() {
+        let ids: Vec<HardwareID> = (0..3).map(make_id).collect();
+        let traces = [
+            Array1::from_vec(vec![0, 1, 2, 0, 4, 0]),
+            Array1::from_vec(vec![0, 0, 0, 0, 0, 0]),
+            Array1::from_vec(vec![6, 0, 0, 9, 10, 11]),
+        ];
+        let reads = [
+            [false, true, true, false, true, false],
+            [false; 6],
+            [true, true, false, true, true, true],
+        ];
+        let sparse = SparseTraces::new(
+            ids.iter()
+                .zip(traces.iter())
+                .zip(reads.iter())
+                .map(|((hw_id, trace), read)| (hw_id, trace.view(), &read[..])),
+            None,
+            |_, sample| sample,
+        );
+
+        // Rebuild the dense traces of the kept pads from the runs
+        let mut dense = Array2::<i16>::zeros((sparse.pads.nrows(), 6));
+        for run in sparse.runs.rows() {
+            let (row, start, length, offset) = (
+                run[0] as usize,
+                run[1] as usize,
+                run[2] as usize,
+                run[3] as usize,
+            );
+            for idx in 0..length {
+                dense[[row, start + idx]] = sparse.samples[offset + idx];
+            }
+        }
+        assert_eq!(sparse.pads.column(3).to_vec(), vec![0, 2]);
+        assert_eq!(dense.row(0), traces[0]);
+        assert_eq!(dense.row(1), traces[2]);
+    }
+}
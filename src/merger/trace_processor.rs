@@ -0,0 +1,188 @@
+use super::calibration::GainCalibration;
+use super::config::{
+    BaselineConfig, Config, DeconvolutionConfig, FpnConfig, HitConfig, PointCloudConfig,
+    SamplingConfig, SaturationConfig,
+};
+use super::deconvolution::ResponseFunction;
+use super::error::{ConfigError, ProcessorError};
+use super::event::Event;
+use super::pad_geometry::PadGeometry;
+use super::pedestal::PedestalTable;
+use super::saturation::SaturationCounter;
+
+/// # TraceProcessor
+/// TraceProcessor applies the configured analysis stages to the traces of each Event before it is written. Stages which are not
+/// enabled in the Config are skipped, so by default the traces are written as they were read.
+///
+/// The stages are applied in order:
+/// 1. Saturation detection
+/// 2. Pedestal subtraction
+/// 3. Fixed pattern noise subtraction
+/// 4. Baseline subtraction
+/// 5. Gain calibration
+/// 6. Hit finding
+/// 7. Deconvolution
+/// 8. Point cloud
+/// 9. Cropping and rebinning the time buckets
+#[derive(Debug, Clone)]
+pub struct TraceProcessor {
+    saturation: SaturationConfig,
+    saturation_counter: SaturationCounter,
+    pedestals: Option<PedestalTable>,
+    calibration: Option<GainCalibration>,
+    geometry: Option<PadGeometry>,
+    response: Option<ResponseFunction>,
+    fpn: FpnConfig,
+    baseline: BaselineConfig,
+    hits: HitConfig,
+    point_cloud: PointCloudConfig,
+    deconvolution: DeconvolutionConfig,
+    sampling: SamplingConfig,
+}
+
+impl TraceProcessor {
+    /// Create the processor for a given run. Loads the pedestal file, calibration, and pad geometry, if needed.
+    pub fn new(config: &Config, run_number: i32) -> Result<Self, ProcessorError> {
+        if !config.sampling.is_valid() {
+            return Err(ConfigError::InvalidOption(String::from(
+                "sampling: first_bucket, last_bucket, rebin, and sampling_frequency",
+            ))
+            .into());
+        }
+        let pedestals = match &config.pedestal_path {
+            Some(path) => {
+                let table = PedestalTable::read(path)?;
+                log::info!(
+                    "Using pedestals from run {} ({} events) in {}",
+                    table.get_run_number(),
+                    table.get_n_events(),
+                    path.display()
+                );
+                Some(table)
+            }
+            None => None,
+        };
+        let calibration = match &config.calibration_path {
+            Some(path) => {
+                log::info!("Using gain calibration from {}", path.display());
+                Some(GainCalibration::new(path)?)
+            }
+            None => None,
+        };
+        let geometry = if config.point_cloud.enabled {
+            if !config.hits.enabled {
+                return Err(ConfigError::MissingOption(String::from("hits: enabled")).into());
+            }
+            if config.point_cloud.drift_velocity <= 0.0 {
+                return Err(ConfigError::MissingOption(String::from(
+                    "point_cloud: drift_velocity",
+                ))
+                .into());
+            }
+            match &config.pad_geometry_path {
+                Some(path) => Some(PadGeometry::new(path)?),
+                None => {
+                    return Err(
+                        ConfigError::MissingOption(String::from("pad_geometry_path")).into(),
+                    )
+                }
+            }
+        } else {
+            None
+        };
+        let response = if config.deconvolution.enabled {
+            match &config.deconvolution.response_path {
+                Some(path) => {
+                    log::info!("Using the AGET response from {}", path.display());
+                    Some(ResponseFunction::read(path)?)
+                }
+                None => {
+                    if config.deconvolution.peaking_time <= 0.0 {
+                        return Err(ConfigError::MissingOption(String::from(
+                            "deconvolution: peaking_time",
+                        ))
+                        .into());
+                    }
+                    Some(ResponseFunction::analytic(
+                        config.deconvolution.peaking_time,
+                        config.sampling.sampling_frequency,
+                    )?)
+                }
+            }
+        } else {
+            None
+        };
+        Ok(TraceProcessor {
+            saturation: config.saturation.clone(),
+            saturation_counter: SaturationCounter::default(),
+            pedestals,
+            calibration,
+            geometry,
+            response,
+            fpn: config.fpn.clone(),
+            baseline: config.baseline.for_run(run_number),
+            hits: config.hits.clone(),
+            point_cloud: config.point_cloud.clone(),
+            deconvolution: config.deconvolution.clone(),
+            sampling: config.sampling.clone(),
+        })
+    }
+
+    /// The saturation stats of the run, if saturation detection is enabled
+    pub fn get_saturation_counter(&self) -> Option<&SaturationCounter> {
+        if self.saturation.enabled {
+            Some(&self.saturation_counter)
+        } else {
+            None
+        }
+    }
+
+    /// The gain calibration in use, if any
+    pub fn get_calibration(&self) -> Option<&GainCalibration> {
+        self.calibration.as_ref()
+    }
+
+    /// The AGET response used for deconvolution, if enabled
+    pub fn get_response(&self) -> Option<&ResponseFunction> {
+        self.response.as_ref()
+    }
+
+    /// The pedestal table in use, if any
+    pub fn get_pedestal_table(&self) -> Option<&PedestalTable> {
+        self.pedestals.as_ref()
+    }
+
+    /// Apply each enabled stage to the event
+    pub fn process(&mut self, event: &mut Event) {
+        if self.saturation.enabled {
+            event.find_saturation(&self.saturation);
+            self.saturation_counter.record(event.iter_saturation());
+        }
+        if let Some(table) = &self.pedestals {
+            event.subtract_pedestals(table);
+        }
+        if self.fpn.enabled {
+            event.subtract_fixed_pattern_noise(self.fpn.keep_fpn_traces);
+        }
+        if self.baseline.enabled {
+            event.subtract_baselines(&self.baseline);
+        }
+        if let Some(calibration) = &self.calibration {
+            event.apply_calibration(calibration);
+        }
+        if self.hits.enabled {
+            event.find_hits(&self.hits);
+        }
+        if let Some(response) = &self.response {
+            event.deconvolve(response, &self.deconvolution, &self.hits);
+        }
+        if let Some(geometry) = &self.geometry {
+            event.build_point_cloud(
+                geometry,
+                &self.point_cloud,
+                self.sampling.sampling_frequency,
+            );
+        }
+        event.resample(&self.sampling);
+    }
+}
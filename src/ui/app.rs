@@ -6,7 +6,7 @@ use std::thread::JoinHandle;
 
 use eframe::egui::{Color32, RichText};
 
-use crate::merger::config::Config;
+use crate::merger::config::{Config, MergeMode};
 use crate::merger::error::ProcessorError;
 
 /// # MergerApp
@@ -45,6 +45,17 @@ impl MergerApp {
         }
     }
 
+    /// Start a worker which only builds the frame indices of the runs
+    fn start_index_worker(&mut self) {
+        if self.worker.is_none() {
+            let prog = self.progress.clone();
+            let conf = self.config.clone();
+            self.worker = Some(std::thread::spawn(|| {
+                crate::merger::process::index(conf, prog)
+            }))
+        }
+    }
+
     /// Stop the processor
     fn stop_worker(&mut self) {
         if let Some(handle) = self.worker.take() {
@@ -207,11 +218,61 @@ impl eframe::App for MergerApp {
                     eframe::egui::widgets::DragValue::new(&mut self.config.last_run_number)
                         .speed(1),
                 );
-                ui.end_row()
+                ui.end_row();
+
+                ui.label("Merge by");
+                ui.horizontal(|ui| {
+                    ui.radio_value(&mut self.config.merge_mode, MergeMode::EventId, "Event ID");
+                    ui.radio_value(
+                        &mut self.config.merge_mode,
+                        MergeMode::Timestamp,
+                        "Timestamp",
+                    );
+                });
+                ui.end_row();
+
+                //Only the timestamp mode needs a tolerance
+                if self.config.merge_mode == MergeMode::Timestamp {
+                    ui.label("Timestamp Tolerance");
+                    ui.add(
+                        eframe::egui::widgets::DragValue::new(&mut self.config.timestamp_tolerance)
+                            .speed(1),
+                    );
+                    ui.end_row();
+                }
+
+                ui.label("Reorder Window");
+                ui.add(
+                    eframe::egui::widgets::DragValue::new(&mut self.config.reorder_window).speed(1),
+                );
+                ui.end_row();
+
+                ui.label("Subtract FPN");
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.config.fpn.enabled, "");
+                    ui.add_enabled(
+                        self.config.fpn.enabled,
+                        eframe::egui::Checkbox::new(
+                            &mut self.config.fpn.keep_fpn_traces,
+                            "Keep FPN traces",
+                        ),
+                    );
+                });
+                ui.end_row();
             });
 
             //Controls
             // You can only click run if there isn't already someone working
+            if ui
+                .add_enabled(
+                    self.worker.is_none(),
+                    eframe::egui::Button::new("Build Index"),
+                )
+                .clicked()
+            {
+                log::info!("Starting frame indexer...");
+                self.start_index_worker();
+            }
             if ui
                 .add_enabled(self.worker.is_none(), eframe::egui::Button::new("Run"))
                 .clicked()